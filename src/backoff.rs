@@ -0,0 +1,70 @@
+//! Doubling delays between retries, shared by the server and the client.
+//!
+//! The server backs off between failed accepts, health port binds and Redis
+//! reconnects; the client between attempts to reach a server that isn't up yet.
+
+// The client never resets its backoff
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+/// Delays that start at `min` and double up to `max`
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub const fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            next: min,
+        }
+    }
+
+    /// The delay before the next attempt; doubles up to `max`
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    /// Starts over from `min`, after an attempt succeeded
+    pub fn reset(&mut self) {
+        self.next = self.min;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(250), Duration::from_secs(1));
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_delay()).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_millis(250),
+                Duration::from_millis(500),
+                Duration::from_secs(1),
+                Duration::from_secs(1),
+                Duration::from_secs(1),
+            ]
+        );
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_backoff_does_not_overflow() {
+        let mut backoff = Backoff::new(Duration::from_millis(10), Duration::from_secs(1));
+        for _ in 0..200 {
+            assert!(backoff.next_delay() <= Duration::from_secs(1));
+        }
+    }
+}
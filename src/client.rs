@@ -1,13 +1,67 @@
+mod backoff;
+mod protocol;
+mod read_error;
+
+use backoff::Backoff;
 use clap::Parser;
 use colored::*;
+use futures_util::stream::FuturesUnordered;
 use futures_util::{SinkExt, StreamExt};
-use std::collections::HashMap;
+use protocol::{ClientMessage, ServerMessage, JSON_SUBPROTOCOL};
+use read_error::describe_read_error;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::io::{self, Write};
-use tokio::sync::mpsc;
-use tokio_tungstenite::connect_async;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async_tls_with_config, Connector};
 
 const DEFAULT_SERVER_URL: &str = "ws://127.0.0.1:8080";
+/// Most connections a single `loadtest` may open
+const MAX_LOADTEST_CONNECTIONS: usize = 1000;
+/// Largest payload `sendrand` will generate
+const MAX_RANDOM_PAYLOAD_BYTES: usize = 64 * 1024 * 1024;
+/// Frames that may wait for a connection's writer before sends are dropped
+const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+/// How long a send waits for room in a full queue before the message is dropped
+const QUEUE_SEND_TIMEOUT: Duration = Duration::from_millis(100);
+/// A line holding only this ends a multi-line `send`
+const MULTILINE_TERMINATOR: &str = ".";
+/// Most connections a single `connect <n>` or `connectmany` may open
+const MAX_CONNECT_BATCH: usize = 20;
+/// Most messages a single `bench` may send
+const MAX_BENCH_MESSAGES: usize = 1_000_000;
+/// How long `bench` waits for the last echoes after sending
+const BENCH_ECHO_TIMEOUT: Duration = Duration::from_secs(10);
+/// Starts every `bench` payload, followed by the message's number
+const BENCH_TAG: &str = "bench ";
+/// A connection that received nothing for longer is shown as stale by `list`;
+/// the server pings every 30 seconds by default, plus some slack
+const STALE_AFTER: Duration = Duration::from_secs(35);
+/// Longest close reason that fits in a close frame next to the 2-byte code
+const MAX_CLOSE_REASON_BYTES: usize = 123;
+/// Payload bytes shown in hex per frame in verbose mode
+const VERBOSE_HEX_BYTES: usize = 64;
 
 #[derive(Parser, Debug)]
 #[command(name = "WebSocket Test Client")]
@@ -16,155 +70,1218 @@ struct Args {
     /// WebSocket server URL to connect to
     #[arg(short, long, default_value = DEFAULT_SERVER_URL)]
     server: String,
+
+    /// Use the typed JSON protocol (negotiates the `json` subprotocol)
+    #[arg(long)]
+    json: bool,
+
+    /// Pretty-print and color received JSON text
+    #[arg(long)]
+    pretty: bool,
+
+    /// Skip TLS certificate verification for wss:// (self-signed test servers)
+    #[arg(long)]
+    insecure: bool,
+
+    /// Frames a connection may queue before sends are dropped
+    #[arg(long, default_value_t = DEFAULT_QUEUE_CAPACITY, value_parser = parse_queue_capacity)]
+    queue_capacity: usize,
+
+    /// Retry the first connection with backoff until the server accepts it
+    #[arg(long)]
+    wait_for_server: bool,
+
+    /// Give up waiting for the server after this many seconds
+    #[arg(long, default_value_t = 60, requires = "wait_for_server")]
+    wait_timeout: u64,
+
+    /// Return from `connect` only once the server's first message (the
+    /// welcome) has been printed
+    #[arg(long)]
+    wait_welcome: bool,
+
+    /// Stop waiting for the welcome after this many seconds
+    #[arg(long, default_value_t = 5, requires = "wait_welcome")]
+    welcome_timeout: u64,
+
+    /// Re-open the connections saved in this file by `export`
+    #[arg(long)]
+    import: Option<String>,
+
+    /// Print results and received messages as JSON lines, without the
+    /// banner or a prompt, for scripts
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Start with `verbose on`: print every received frame, pings and pongs included
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+fn parse_queue_capacity(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(capacity) => Ok(capacity),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
 #[derive(Debug)]
 enum Command {
-    Connect,
-    ConnectMultiple(usize),
-    Close(usize),
+    /// `connect [url] [count]`; `None` uses `--server`
+    Connect(Option<String>),
+    ConnectMultiple(Option<String>, usize),
+    /// One connection to each URL
+    ConnectMany(Vec<String>),
+    LoadTest(usize, usize),
+    /// `close <id> [code] [reason]`; `None` sends a close frame without a code
+    Close(usize, Option<CloseFrame<'static>>),
     CloseAll,
     List,
     Send(usize, String),
+    /// `send <id>` without a message: read lines until a lone `.`
+    SendMultiline(usize),
+    SendRandom(usize, usize, PayloadKind),
+    /// `bench <id> <count>`: send `count` messages and time their echoes
+    Bench(usize, usize),
+    /// `label <id> [name]`; `None` clears the label
+    Label(usize, Option<String>),
+    /// `export <file>`: save the open connections' URLs and labels
+    Export(String),
+    /// `import <file>`: re-open the connections saved by `export`
+    Import(String),
+    /// `verbose on|off`: print every received frame
+    Verbose(bool),
     Help,
     Quit,
 }
 
+/// Frame type of a generated payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum PayloadKind {
+    /// Random printable ASCII characters
+    Text,
+    /// Random bytes
+    Binary,
+}
+
+/// Per-connection settings taken from the command line
+#[derive(Debug, Clone, Copy)]
+struct ConnectOptions {
+    /// Negotiate the `json` subprotocol and decode its frames
+    json: bool,
+    /// Pretty-print received JSON text
+    pretty: bool,
+    /// Capacity of the per-connection send queue
+    queue_capacity: usize,
+    /// Accept any certificate on `wss://` connections
+    insecure: bool,
+    /// With `--wait-welcome`, how long a new connection's caller waits for
+    /// the server's first message; `None` prints it whenever it arrives
+    welcome_timeout: Option<Duration>,
+}
+
+/// Why `create_connection` failed
+#[derive(Debug)]
+enum ConnectError {
+    /// The server answered the upgrade with 503 because its connection limit is reached
+    AtCapacity,
+    /// Nothing is listening at the server address
+    Refused,
+    Other(tungstenite::Error),
+}
+
+impl From<tungstenite::Error> for ConnectError {
+    fn from(error: tungstenite::Error) -> Self {
+        match &error {
+            tungstenite::Error::Http(response)
+                if response.status() == StatusCode::SERVICE_UNAVAILABLE =>
+            {
+                ConnectError::AtCapacity
+            }
+            tungstenite::Error::Io(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                ConnectError::Refused
+            }
+            _ => ConnectError::Other(error),
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectError::AtCapacity => write!(f, "Server at capacity (503)"),
+            ConnectError::Refused => write!(f, "Connection refused - is the server running?"),
+            ConnectError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+/// Set by `--quiet`: events are printed as JSON lines and there is no prompt
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Set by `verbose on` (or `--verbose`): every received frame is printed
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Prints a progress line that scripts have no use for; nothing with `--quiet`
+fn note(text: impl std::fmt::Display) {
+    if !quiet() {
+        println!("{}", text);
+    }
+}
+
+/// Something the client did or saw. People get a decorated line, scripts
+/// (`--quiet`) one JSON object per line, e.g. `{"event":"recv","id":3,"text":"hi"}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    Connected {
+        id: usize,
+        url: String,
+        /// Mention `url` in the human line, for commands that reach several servers
+        #[serde(skip)]
+        show_url: bool,
+    },
+    Sent {
+        id: usize,
+        text: String,
+    },
+    SentRandom {
+        id: usize,
+        size: usize,
+        kind: PayloadKind,
+    },
+    /// A text message; unless `--quiet` it is already rendered for display
+    Recv {
+        id: usize,
+        text: String,
+    },
+    RecvBinary {
+        id: usize,
+        len: usize,
+    },
+    /// Any received frame, printed in verbose mode; `hex` holds at most the
+    /// first `VERBOSE_HEX_BYTES` bytes of the payload
+    Frame {
+        id: usize,
+        opcode: &'static str,
+        len: usize,
+        hex: String,
+    },
+    Verbose {
+        enabled: bool,
+    },
+    /// Closed by the client
+    Closed {
+        id: usize,
+    },
+    ClosedAll {
+        count: usize,
+    },
+    ServerClosed {
+        id: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        code: Option<u16>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    Connections {
+        connections: Vec<ConnectionStatus>,
+    },
+    Labeled {
+        id: usize,
+        label: Option<String>,
+    },
+    Exported {
+        path: String,
+        count: usize,
+    },
+    WaitingForServer {
+        attempt: u32,
+        error: String,
+        #[serde(rename = "retry_ms", serialize_with = "serialize_millis")]
+        retry_in: Duration,
+    },
+    LoadTest {
+        succeeded: usize,
+        failed: usize,
+        #[serde(rename = "elapsed_ms", serialize_with = "serialize_millis")]
+        elapsed: Duration,
+        /// Occurrences of each distinct error
+        errors: BTreeMap<String, usize>,
+    },
+    Bench {
+        id: usize,
+        sent: u64,
+        echoed: u64,
+        #[serde(rename = "elapsed_ms", serialize_with = "serialize_millis")]
+        elapsed: Duration,
+    },
+    Warning {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<usize>,
+        message: String,
+    },
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<usize>,
+        message: String,
+    },
+}
+
+/// One line of `list`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ConnectionStatus {
+    id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    url: String,
+    health: Health,
+    queue_depth: usize,
+    queue_capacity: usize,
+    /// Time since the last frame from the server
+    idle_ms: u64,
+}
+
+fn serialize_millis<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+impl Event {
+    fn error(id: Option<usize>, message: impl Into<String>) -> Self {
+        Event::Error {
+            id,
+            message: message.into(),
+        }
+    }
+
+    fn not_found(id: usize) -> Self {
+        Event::error(Some(id), format!("Connection #{} not found", id))
+    }
+
+    fn print(&self) {
+        if quiet() {
+            println!("{}", self.to_json());
+        } else {
+            println!("{}", self.human());
+        }
+    }
+
+    /// Like `print`, for events from a read task: the human line goes below
+    /// the prompt, which is then drawn again
+    fn print_over_prompt(&self) {
+        if quiet() {
+            self.print();
+            return;
+        }
+        println!("\n{}", self.human());
+        print!("{} ", ">".bright_green().bold());
+        io::stdout().flush().unwrap();
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("events always serialize")
+    }
+
+    /// The decorated line(s) shown without `--quiet`
+    fn human(&self) -> String {
+        match self {
+            Event::Connected { id, url, show_url } => {
+                let to = if *show_url {
+                    format!(" to {}", url)
+                } else {
+                    String::new()
+                };
+                format!("{} Connection #{} established{}", "✓".green(), id, to)
+            }
+            Event::Sent { id, text } => {
+                format!("{} Sent to connection #{}: {}", "✓".green(), id, text)
+            }
+            Event::SentRandom { id, size, kind } => format!(
+                "{} Sent {} random {} bytes to connection #{}",
+                "✓".green(),
+                size,
+                if *kind == PayloadKind::Text {
+                    "text"
+                } else {
+                    "binary"
+                },
+                id
+            ),
+            Event::Recv { id, text } => format!("{} Connection #{}: {}", "←".cyan(), id, text),
+            Event::RecvBinary { id, len } => {
+                format!("{} Connection #{}: Received {} bytes", "←".cyan(), id, len)
+            }
+            Event::Frame {
+                id,
+                opcode,
+                len,
+                hex,
+            } => {
+                let more = len.saturating_sub(VERBOSE_HEX_BYTES);
+                let more = if more > 0 {
+                    format!(" (+{} bytes)", more)
+                } else {
+                    String::new()
+                };
+                format!(
+                    "{} Connection #{}: {} frame, {} bytes{}{}{}",
+                    "⇠".dimmed(),
+                    id,
+                    opcode.bright_magenta(),
+                    len,
+                    if hex.is_empty() { "" } else { ": " },
+                    hex.dimmed(),
+                    more
+                )
+            }
+            Event::Verbose { enabled } => format!(
+                "{} Verbose frame output {}",
+                "✓".green(),
+                if *enabled { "on" } else { "off" }
+            ),
+            Event::Closed { id } => format!("{} Closed connection #{}", "✓".green(), id),
+            Event::ClosedAll { count } => {
+                format!("{} Closed {} connection(s)", "✓".green(), count)
+            }
+            Event::ServerClosed { id, code, reason } => {
+                let detail = match (code, reason) {
+                    (Some(code), Some(reason)) => format!(" with code {} ({})", code, reason),
+                    (Some(code), None) => format!(" with code {}", code),
+                    _ => String::new(),
+                };
+                format!(
+                    "{} Connection #{} closed by server{}",
+                    "!".yellow(),
+                    id,
+                    detail
+                )
+            }
+            Event::Connections { connections } if connections.is_empty() => {
+                "No active connections".to_string()
+            }
+            Event::Connections { connections } => {
+                let mut lines = vec!["Active connections:".bright_yellow().to_string()];
+                for conn in connections {
+                    let label = conn
+                        .label
+                        .as_ref()
+                        .map(|label| format!(" [{}]", label))
+                        .unwrap_or_default();
+                    lines.push(format!(
+                        "  {} Connection #{}{} to {} (queue {}/{}, last frame {:.0?} ago)",
+                        conn.health.indicator(),
+                        conn.id,
+                        label,
+                        conn.url,
+                        conn.queue_depth,
+                        conn.queue_capacity,
+                        Duration::from_millis(conn.idle_ms)
+                    ));
+                }
+                lines.join("\n")
+            }
+            Event::Labeled {
+                id,
+                label: Some(label),
+            } => format!("{} Labeled connection #{} '{}'", "✓".green(), id, label),
+            Event::Labeled { id, label: None } => {
+                format!("{} Cleared the label of connection #{}", "✓".green(), id)
+            }
+            Event::Exported { path, count } => format!(
+                "{} Exported {} connection(s) to {}",
+                "✓".green(),
+                count,
+                path
+            ),
+            Event::WaitingForServer {
+                attempt,
+                error,
+                retry_in,
+            } => format!(
+                "{} Waiting for server (attempt {}: {}), retrying in {:.1?}",
+                "…".yellow(),
+                attempt,
+                error,
+                retry_in
+            ),
+            Event::LoadTest {
+                succeeded,
+                failed,
+                elapsed,
+                errors,
+            } => {
+                let mut lines = vec![format!(
+                    "{} Load test finished in {:.2?}: {} succeeded, {} failed",
+                    if errors.is_empty() {
+                        "✓".green()
+                    } else {
+                        "!".yellow()
+                    },
+                    elapsed,
+                    succeeded,
+                    failed
+                )];
+                for (error, occurrences) in errors {
+                    lines.push(format!("  {} {}x {}", "✗".red(), occurrences, error));
+                }
+                lines.join("\n")
+            }
+            Event::Bench {
+                id,
+                sent,
+                echoed,
+                elapsed,
+            } => {
+                let lost = sent - echoed;
+                format!(
+                    "{} Bench #{}: {} sent, {} echoed in {:.2?} - {} msg/s, {} lost ({:.1}%)",
+                    if lost == 0 {
+                        "✓".green()
+                    } else {
+                        "!".yellow()
+                    },
+                    id,
+                    sent,
+                    echoed,
+                    elapsed,
+                    format!("{:.0}", *echoed as f64 / elapsed.as_secs_f64())
+                        .bright_cyan()
+                        .bold(),
+                    if lost == 0 {
+                        lost.to_string().green()
+                    } else {
+                        lost.to_string().red()
+                    },
+                    if *sent == 0 {
+                        0.0
+                    } else {
+                        lost as f64 * 100.0 / *sent as f64
+                    }
+                )
+            }
+            Event::Warning { message, .. } => format!("{} {}", "!".yellow(), message),
+            Event::Error { message, .. } => format!("{} {}", "✗".red(), message),
+        }
+    }
+}
+
+/// The connections `export` writes to a file and `import` re-opens
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Session {
+    connections: Vec<SavedConnection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SavedConnection {
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+impl Session {
+    /// The open connections, ordered by id
+    fn of(connections: &HashMap<usize, Connection>) -> Self {
+        let mut conns: Vec<_> = connections.values().collect();
+        conns.sort_by_key(|conn| conn.id);
+        Self {
+            connections: conns
+                .into_iter()
+                .map(|conn| SavedConnection {
+                    url: conn.url.clone(),
+                    label: conn.label.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).expect("sessions always serialize");
+        std::fs::write(path, json + "\n")
+    }
+
+    fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&json).map_err(|e| e.to_string())
+    }
+}
+
 struct Connection {
     id: usize,
-    tx: mpsc::UnboundedSender<Message>,
+    /// Server the connection was opened to
+    url: String,
+    /// Name given with `label`, shown by `list` and kept by `export`
+    label: Option<String>,
+    tx: mpsc::Sender<Message>,
+    /// Updated by the connection's read task
+    received: Arc<ReceivedMessages>,
+    /// The first message from the server, handed over by the read task
+    /// instead of printed when the caller waits for it
+    welcome: Option<(oneshot::Receiver<Event>, Duration)>,
+}
+
+/// What a connection's read task tells the REPL about incoming messages
+#[derive(Debug)]
+struct ReceivedMessages {
+    /// Text and binary messages received so far
+    count: AtomicU64,
+    /// Set while `bench` runs: its echoes are counted instead of printed
+    benching: AtomicBool,
+    /// Echoes of `bench` payloads received while `benching`
+    bench_echoes: AtomicU64,
+    /// When the last frame of any kind, server pings included, arrived; the
+    /// time the connection opened until then
+    last_seen: Mutex<Instant>,
+}
+
+impl Default for ReceivedMessages {
+    fn default() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            benching: AtomicBool::new(false),
+            bench_echoes: AtomicU64::new(0),
+            last_seen: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+/// How `list` rates a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Health {
+    /// Received a frame within `STALE_AFTER`
+    Active,
+    /// Quiet for longer, so it may have died silently
+    Stale,
+    /// The read or write side has stopped
+    Closed,
+}
+
+impl Health {
+    fn of(idle: Duration, closed: bool) -> Self {
+        if closed {
+            Health::Closed
+        } else if idle <= STALE_AFTER {
+            Health::Active
+        } else {
+            Health::Stale
+        }
+    }
+
+    fn indicator(self) -> ColoredString {
+        match self {
+            Health::Active => "●".green(),
+            Health::Stale => "●".yellow(),
+            Health::Closed => "●".red(),
+        }
+    }
+}
+
+impl Connection {
+    /// Frames queued but not yet written
+    fn queue_depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    /// Time since the last frame from the server
+    fn idle(&self) -> Duration {
+        self.received.last_seen.lock().unwrap().elapsed()
+    }
+
+    fn health(&self) -> Health {
+        Health::of(self.idle(), self.tx.is_closed())
+    }
+
+    /// The event announcing a new connection
+    fn connected(&self, show_url: bool) -> Event {
+        Event::Connected {
+            id: self.id,
+            url: self.url.clone(),
+            show_url,
+        }
+    }
+
+    fn status(&self) -> ConnectionStatus {
+        ConnectionStatus {
+            id: self.id,
+            label: self.label.clone(),
+            url: self.url.clone(),
+            health: self.health(),
+            queue_depth: self.queue_depth(),
+            queue_capacity: self.tx.max_capacity(),
+            idle_ms: self.idle().as_millis() as u64,
+        }
+    }
+
+    /// With `--wait-welcome`, prints the server's first message once it
+    /// arrives, so the next command runs after it. A connection that closes
+    /// first has its close printed by the read task
+    async fn wait_for_welcome(&mut self) {
+        let Some((welcome, timeout)) = self.welcome.take() else {
+            return;
+        };
+        match tokio::time::timeout(timeout, welcome).await {
+            Ok(Ok(event)) => event.print(),
+            Ok(Err(_)) => {}
+            // Dropping the receiver makes the read task print it when it comes
+            Err(_) => Event::Warning {
+                id: Some(self.id),
+                message: format!(
+                    "No welcome on #{} within {}s - not waiting for it",
+                    self.id,
+                    timeout.as_secs_f64()
+                ),
+            }
+            .print(),
+        }
+    }
+
+    /// Queues `message`, waiting up to `QUEUE_SEND_TIMEOUT` for room; prints why it was
+    /// dropped and returns false if it was
+    async fn queue(&self, message: Message) -> bool {
+        match self.tx.send_timeout(message, QUEUE_SEND_TIMEOUT).await {
+            Ok(()) => true,
+            Err(mpsc::error::SendTimeoutError::Timeout(_)) => {
+                Event::error(
+                    Some(self.id),
+                    format!(
+                        "Send queue of #{} stayed full for {}ms ({} pending) - message dropped",
+                        self.id,
+                        QUEUE_SEND_TIMEOUT.as_millis(),
+                        self.queue_depth()
+                    ),
+                )
+                .print();
+                false
+            }
+            Err(mpsc::error::SendTimeoutError::Closed(_)) => {
+                Event::error(
+                    Some(self.id),
+                    format!("Failed to send message to #{}", self.id),
+                )
+                .print();
+                false
+            }
+        }
+    }
+
+    /// Starts the close handshake with `frame`; with a full queue the
+    /// connection is just dropped
+    fn close(self, frame: Option<CloseFrame<'static>>) {
+        if self.tx.try_send(Message::Close(frame)).is_err() {
+            Event::Warning {
+                id: Some(self.id),
+                message: format!(
+                    "Send queue of #{} is full - dropping it without a close handshake",
+                    self.id
+                ),
+            }
+            .print();
+        }
+    }
+}
+
+/// Command names offered by tab completion
+const COMMAND_NAMES: &[&str] = &[
+    "connect",
+    "connectmany",
+    "close",
+    "list",
+    "send",
+    "sendrand",
+    "bench",
+    "loadtest",
+    "label",
+    "export",
+    "import",
+    "verbose",
+    "help",
+    "quit",
+    "exit",
+];
+
+/// Tab completion for command names and the ids of open connections
+struct CommandCompleter {
+    /// Kept in sync with the `connections` map in `main`
+    connection_ids: Arc<Mutex<BTreeSet<usize>>>,
+}
+
+impl Completer for CommandCompleter {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let ids = self.connection_ids.lock().unwrap();
+        Ok(complete_input(&line[..pos], &ids))
+    }
+}
+
+impl Hinter for CommandCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for CommandCompleter {}
+
+impl Validator for CommandCompleter {}
+
+impl Helper for CommandCompleter {}
+
+/// Completes the last word of `line`: a command name for the first word, or a
+/// connection id for the first argument of `send`/`close`/`label`
+fn complete_input(line: &str, connection_ids: &BTreeSet<usize>) -> (usize, Vec<String>) {
+    // The whitespace may be wider than one byte, e.g. U+3000
+    let start = line
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace())
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let word = &line[start..];
+    let previous: Vec<&str> = line[..start].split_whitespace().collect();
+
+    let candidates: Vec<String> = match previous.as_slice() {
+        [] => COMMAND_NAMES.iter().map(|name| name.to_string()).collect(),
+        [command] => match command.to_lowercase().as_str() {
+            "send" | "s" | "sendrand" | "bench" | "label" => {
+                connection_ids.iter().map(|id| id.to_string()).collect()
+            }
+            "close" => std::iter::once("all".to_string())
+                .chain(connection_ids.iter().map(|id| id.to_string()))
+                .collect(),
+            "verbose" => vec!["on".to_string(), "off".to_string()],
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+
+    let matches = candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(word))
+        .collect();
+    (start, matches)
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    QUIET.store(args.quiet, Ordering::Relaxed);
+    VERBOSE.store(args.verbose, Ordering::Relaxed);
 
-    println!("{}", "=== WebSocket Test Client ===".bright_blue().bold());
-    println!("Server URL: {}", args.server.bright_cyan());
-    println!("Type 'help' for available commands\n");
+    note("=== WebSocket Test Client ===".bright_blue().bold());
+    note(format!("Server URL: {}", args.server.bright_cyan()));
+    note("Type 'help' for available commands\n");
 
     let server_url = args.server.clone();
+    let options = ConnectOptions {
+        json: args.json,
+        pretty: args.pretty,
+        queue_capacity: args.queue_capacity,
+        insecure: args.insecure,
+        welcome_timeout: args
+            .wait_welcome
+            .then(|| Duration::from_secs(args.welcome_timeout)),
+    };
+    if args.insecure {
+        note(format!(
+            "{} TLS certificates are not verified (--insecure)\n",
+            "!".yellow()
+        ));
+    }
     let mut connections: HashMap<usize, Connection> = HashMap::new();
     let mut next_id = 1;
 
+    if args.wait_for_server {
+        let timeout = Duration::from_secs(args.wait_timeout);
+        match wait_for_server(next_id, &server_url, options, timeout).await {
+            Some((mut conn, handle)) => {
+                conn.connected(false).print();
+                conn.wait_for_welcome().await;
+                connections.insert(conn.id, conn);
+                tokio::spawn(handle);
+                next_id += 1;
+            }
+            None => {
+                Event::error(
+                    None,
+                    format!(
+                        "Server did not accept a connection within {} seconds",
+                        args.wait_timeout
+                    ),
+                )
+                .print();
+                return;
+            }
+        }
+    }
+
+    if let Some(path) = &args.import {
+        import_session(path, options, &mut next_id, &mut connections).await;
+    }
+
+    let connection_ids = Arc::new(Mutex::new(BTreeSet::new()));
+    let mut editor: Editor<CommandCompleter, DefaultHistory> =
+        Editor::new().expect("failed to initialize line editor");
+    editor.set_helper(Some(CommandCompleter {
+        connection_ids: connection_ids.clone(),
+    }));
+    let prompt = if args.quiet {
+        String::new()
+    } else {
+        format!("{} ", ">".bright_green().bold())
+    };
+
     loop {
-        print!("{} ", ">".bright_green().bold());
-        io::stdout().flush().unwrap();
+        // Publish the live connection ids for tab completion
+        *connection_ids.lock().unwrap() = connections.keys().copied().collect();
 
-        let mut input = String::new();
-        if io::stdin().read_line(&mut input).is_err() {
-            continue;
-        }
+        let (returned_editor, line) = read_line(editor, prompt.clone()).await;
+        editor = returned_editor;
+
+        let input = match line {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => "quit".to_string(),
+            Err(e) => {
+                Event::error(None, format!("Failed to read input: {}", e)).print();
+                break;
+            }
+        };
 
         let input = input.trim();
         if input.is_empty() {
             continue;
         }
+        let _ = editor.add_history_entry(input);
 
         match parse_command(input) {
-            Ok(Command::Connect) => match create_connection(next_id, &server_url).await {
-                Ok((id, tx, handle)) => {
-                    connections.insert(id, Connection { id, tx });
-                    tokio::spawn(handle);
-                    println!("{} Connection #{} established", "✓".green(), id);
-                    next_id += 1;
-                }
-                Err(e) => {
-                    println!("{} Failed to connect: {}", "✗".red(), e);
+            Ok(Command::Connect(url)) => {
+                let url = url.unwrap_or_else(|| server_url.clone());
+                match create_connection(next_id, &url, options).await {
+                    Ok((mut conn, handle)) => {
+                        conn.connected(false).print();
+                        conn.wait_for_welcome().await;
+                        connections.insert(conn.id, conn);
+                        tokio::spawn(handle);
+                        next_id += 1;
+                    }
+                    Err(e) => {
+                        Event::error(None, format!("Failed to connect: {}", e)).print();
+                    }
                 }
-            },
-            Ok(Command::ConnectMultiple(count)) => {
-                if count == 0 || count > 20 {
-                    println!("{} Please specify a number between 1 and 20", "✗".red());
+            }
+            Ok(Command::ConnectMultiple(url, count)) => {
+                if count == 0 || count > MAX_CONNECT_BATCH {
+                    Event::error(
+                        None,
+                        format!(
+                            "Please specify a number between 1 and {}",
+                            MAX_CONNECT_BATCH
+                        ),
+                    )
+                    .print();
                     continue;
                 }
-                println!("Creating {} connections...", count);
-                for _ in 0..count {
-                    match create_connection(next_id, &server_url).await {
-                        Ok((id, tx, handle)) => {
-                            connections.insert(id, Connection { id, tx });
+                let url = url.unwrap_or_else(|| server_url.clone());
+                note(format!("Creating {} connections...", count));
+                for created in 0..count {
+                    match create_connection(next_id, &url, options).await {
+                        Ok((mut conn, handle)) => {
+                            conn.connected(false).print();
+                            conn.wait_for_welcome().await;
+                            connections.insert(conn.id, conn);
                             tokio::spawn(handle);
-                            println!("{} Connection #{} established", "✓".green(), id);
                             next_id += 1;
                         }
+                        Err(ConnectError::AtCapacity) => {
+                            Event::error(
+                                None,
+                                format!(
+                                    "Server at capacity (503) - stopped after {} of {} connections",
+                                    created, count
+                                ),
+                            )
+                            .print();
+                            break;
+                        }
                         Err(e) => {
-                            println!("{} Failed to connect: {}", "✗".red(), e);
+                            Event::error(None, format!("Failed to connect: {}", e)).print();
                             break;
                         }
                     }
                 }
             }
-            Ok(Command::Close(id)) => {
+            Ok(Command::ConnectMany(urls)) => {
+                note(format!("Creating {} connections...", urls.len()));
+                for url in urls {
+                    match create_connection(next_id, &url, options).await {
+                        Ok((mut conn, handle)) => {
+                            conn.connected(true).print();
+                            conn.wait_for_welcome().await;
+                            connections.insert(conn.id, conn);
+                            tokio::spawn(handle);
+                            next_id += 1;
+                        }
+                        // Each URL is a separate server, so keep going
+                        Err(e) => {
+                            Event::error(None, format!("Failed to connect to {}: {}", url, e))
+                                .print()
+                        }
+                    }
+                }
+            }
+            Ok(Command::LoadTest(count, concurrency)) => {
+                load_test(
+                    &server_url,
+                    options,
+                    next_id,
+                    count,
+                    concurrency,
+                    &mut connections,
+                )
+                .await;
+                next_id += count;
+            }
+            Ok(Command::Close(id, frame)) => {
                 if let Some(conn) = connections.remove(&id) {
-                    let _ = conn.tx.send(Message::Close(None));
-                    println!("{} Closed connection #{}", "✓".green(), id);
+                    conn.close(frame);
+                    Event::Closed { id }.print();
                 } else {
-                    println!("{} Connection #{} not found", "✗".red(), id);
+                    Event::not_found(id).print();
                 }
             }
             Ok(Command::CloseAll) => {
                 let count = connections.len();
                 for (_, conn) in connections.drain() {
-                    let _ = conn.tx.send(Message::Close(None));
+                    conn.close(None);
                 }
-                println!("{} Closed {} connection(s)", "✓".green(), count);
+                Event::ClosedAll { count }.print();
             }
             Ok(Command::List) => {
-                if connections.is_empty() {
-                    println!("No active connections");
-                } else {
-                    println!("{}", "Active connections:".bright_yellow());
-                    let mut ids: Vec<_> = connections.keys().collect();
-                    ids.sort();
-                    for id in ids {
-                        println!("  • Connection #{}", id);
-                    }
+                let mut conns: Vec<_> = connections.values().collect();
+                conns.sort_by_key(|conn| conn.id);
+                Event::Connections {
+                    connections: conns.into_iter().map(Connection::status).collect(),
                 }
+                .print();
             }
             Ok(Command::Send(id, message)) => {
+                send_text(&connections, id, options.json, &message).await
+            }
+            Ok(Command::SendMultiline(id)) => {
+                if !connections.contains_key(&id) {
+                    Event::not_found(id).print();
+                    continue;
+                }
+                note(format!(
+                    "Enter the message for connection #{}, end with a line containing only '.' (Ctrl-C cancels)",
+                    id
+                ));
+                let continuation = if args.quiet { "" } else { "... " };
+                let mut lines = Vec::new();
+                let cancelled = loop {
+                    let (returned_editor, line) = read_line(editor, continuation.to_string()).await;
+                    editor = returned_editor;
+                    match line {
+                        Ok(line) if line == MULTILINE_TERMINATOR => break false,
+                        Ok(line) => lines.push(line),
+                        Err(_) => break true,
+                    }
+                };
+                if cancelled {
+                    Event::Warning {
+                        id: Some(id),
+                        message: "Message discarded".to_string(),
+                    }
+                    .print();
+                } else {
+                    send_text(&connections, id, options.json, &lines.join("\n")).await;
+                }
+            }
+            Ok(Command::SendRandom(id, size, kind)) => {
                 if let Some(conn) = connections.get(&id) {
-                    if conn.tx.send(Message::Text(message.clone())).is_ok() {
-                        println!("{} Sent to connection #{}: {}", "✓".green(), id, message);
-                    } else {
-                        println!("{} Failed to send message to #{}", "✗".red(), id);
+                    if conn.queue(random_payload(size, kind)).await {
+                        Event::SentRandom { id, size, kind }.print();
                     }
                 } else {
-                    println!("{} Connection #{} not found", "✗".red(), id);
+                    Event::not_found(id).print();
                 }
             }
+            Ok(Command::Bench(id, count)) => {
+                if let Some(conn) = connections.get(&id) {
+                    bench(conn, options.json, count).await;
+                } else {
+                    Event::not_found(id).print();
+                }
+            }
+            Ok(Command::Label(id, label)) => match connections.get_mut(&id) {
+                Some(conn) => {
+                    conn.label = label.clone();
+                    Event::Labeled { id, label }.print();
+                }
+                None => Event::not_found(id).print(),
+            },
+            Ok(Command::Export(path)) => {
+                let session = Session::of(&connections);
+                match session.save(Path::new(&path)) {
+                    Ok(()) => Event::Exported {
+                        count: session.connections.len(),
+                        path,
+                    }
+                    .print(),
+                    Err(e) => {
+                        Event::error(None, format!("Failed to write {}: {}", path, e)).print()
+                    }
+                }
+            }
+            Ok(Command::Import(path)) => {
+                import_session(&path, options, &mut next_id, &mut connections).await;
+            }
+            Ok(Command::Verbose(enabled)) => {
+                VERBOSE.store(enabled, Ordering::Relaxed);
+                Event::Verbose { enabled }.print();
+            }
             Ok(Command::Help) => {
                 print_help();
             }
             Ok(Command::Quit) => {
-                println!("Closing all connections and exiting...");
+                note("Closing all connections and exiting...");
                 for (_, conn) in connections.drain() {
-                    let _ = conn.tx.send(Message::Close(None));
+                    conn.close(None);
                 }
                 tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
                 break;
             }
             Err(e) => {
-                println!("{} {}", "✗".red(), e);
+                Event::error(None, e).print();
             }
         }
     }
 }
 
+/// Runs one readline off the async runtime, since it blocks
+async fn read_line(
+    mut editor: Editor<CommandCompleter, DefaultHistory>,
+    prompt: String,
+) -> (
+    Editor<CommandCompleter, DefaultHistory>,
+    rustyline::Result<String>,
+) {
+    tokio::task::spawn_blocking(move || {
+        let line = editor.readline(&prompt);
+        (editor, line)
+    })
+    .await
+    .expect("line editor task panicked")
+}
+
+/// Re-opens the connections saved in `path` under fresh ids. A connection
+/// that fails is reported and skipped.
+async fn import_session(
+    path: &str,
+    options: ConnectOptions,
+    next_id: &mut usize,
+    connections: &mut HashMap<usize, Connection>,
+) {
+    let session = match Session::load(Path::new(path)) {
+        Ok(session) => session,
+        Err(e) => {
+            Event::error(None, format!("Failed to import {}: {}", path, e)).print();
+            return;
+        }
+    };
+    note(format!(
+        "Importing {} connection(s) from {}...",
+        session.connections.len(),
+        path
+    ));
+    for saved in session.connections {
+        match create_connection(*next_id, &saved.url, options).await {
+            Ok((mut conn, handle)) => {
+                conn.connected(true).print();
+                conn.wait_for_welcome().await;
+                conn.label = saved.label;
+                connections.insert(conn.id, conn);
+                tokio::spawn(handle);
+                *next_id += 1;
+            }
+            Err(e) => {
+                Event::error(None, format!("Failed to connect to {}: {}", saved.url, e)).print()
+            }
+        }
+    }
+}
+
+/// Sends `message` as one text frame, wrapped in an echo envelope with `--json`
+async fn send_text(connections: &HashMap<usize, Connection>, id: usize, json: bool, message: &str) {
+    let Some(conn) = connections.get(&id) else {
+        Event::not_found(id).print();
+        return;
+    };
+    let frame = if json {
+        ClientMessage::Echo {
+            payload: serde_json::Value::String(message.to_string()),
+        }
+        .to_json()
+    } else {
+        message.to_string()
+    };
+    if conn.queue(Message::Text(frame)).await {
+        Event::Sent {
+            id,
+            text: message.to_string(),
+        }
+        .print();
+    }
+}
+
 async fn create_connection(
     id: usize,
     url: &str,
-) -> Result<
-    (
-        usize,
-        mpsc::UnboundedSender<Message>,
-        tokio::task::JoinHandle<()>,
-    ),
-    Box<dyn std::error::Error>,
-> {
-    let (ws_stream, _) = connect_async(url).await?;
+    options: ConnectOptions,
+) -> Result<(Connection, tokio::task::JoinHandle<()>), ConnectError> {
+    let mut request = url.into_client_request()?;
+    if options.json {
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            HeaderValue::from_static(JSON_SUBPROTOCOL),
+        );
+    }
+    let connector = connector_for(request.uri().scheme_str(), options.insecure);
+    let (ws_stream, _) =
+        connect_async_tls_with_config(request, None, false, Some(connector)).await?;
     let (mut write, mut read) = ws_stream.split();
 
-    let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+    let (tx, mut rx) = mpsc::channel::<Message>(options.queue_capacity);
+    let received = Arc::new(ReceivedMessages::default());
+    let counter = received.clone();
+    let (welcome_tx, welcome_rx) = oneshot::channel();
+    let mut welcome_tx = options.welcome_timeout.map(|_| welcome_tx);
+    // Prints a received message, or hands the first one to a waiting `connect`
+    let mut show = move |event: Event| match welcome_tx.take() {
+        Some(tx) => {
+            if let Err(event) = tx.send(event) {
+                event.print_over_prompt();
+            }
+        }
+        None => event.print_over_prompt(),
+    };
 
     let handle = tokio::spawn(async move {
         loop {
@@ -173,36 +1290,57 @@ async fn create_connection(
                 msg = read.next() => {
                     match msg {
                         Some(Ok(message)) => {
+                            *counter.last_seen.lock().unwrap() = Instant::now();
+                            if message.is_text() || message.is_binary() {
+                                counter.count.fetch_add(1, Ordering::Relaxed);
+                            }
+                            // Anything else that arrives during a bench is still shown
+                            if let Message::Text(text) = &message {
+                                if counter.benching.load(Ordering::Relaxed) && is_bench_echo(text) {
+                                    counter.bench_echoes.fetch_add(1, Ordering::Relaxed);
+                                    continue;
+                                }
+                            }
+                            if verbose() {
+                                frame_event(id, &message).print_over_prompt();
+                            }
                             match message {
                                 Message::Text(text) => {
-                                    println!("\n{} Connection #{}: {}", "←".cyan(), id, text);
-                                    print!("{} ", ">".bright_green().bold());
-                                    io::stdout().flush().unwrap();
+                                    // Scripts get the text exactly as sent
+                                    let text = match pretty_json(&text).filter(|_| options.pretty && !quiet()) {
+                                        // Start multi-line output on its own line
+                                        Some(pretty) => format!("\n{}", pretty),
+                                        None if options.json && !quiet() => describe_server_message(&text),
+                                        None => text,
+                                    };
+                                    show(Event::Recv { id, text });
                                 }
                                 Message::Binary(data) => {
-                                    println!("\n{} Connection #{}: Received {} bytes", "←".cyan(), id, data.len());
-                                    print!("{} ", ">".bright_green().bold());
-                                    io::stdout().flush().unwrap();
+                                    show(Event::RecvBinary { id, len: data.len() });
                                 }
-                                Message::Close(_) => {
-                                    println!("\n{} Connection #{} closed by server", "!".yellow(), id);
-                                    print!("{} ", ">".bright_green().bold());
-                                    io::stdout().flush().unwrap();
+                                Message::Close(frame) => {
+                                    Event::ServerClosed {
+                                        id,
+                                        code: frame.as_ref().map(|frame| frame.code.into()),
+                                        reason: frame
+                                            .map(|frame| frame.reason.into_owned())
+                                            .filter(|reason| !reason.is_empty()),
+                                    }
+                                    .print_over_prompt();
                                     break;
                                 }
                                 Message::Ping(_) => {
-                                    // Pings are handled automatically by the library
+                                    // Pings are answered by the library; shown only in verbose mode
                                 }
                                 Message::Pong(_) => {
-                                    // Pong received
+                                    // Shown only in verbose mode
                                 }
                                 _ => {}
                             }
                         }
                         Some(Err(e)) => {
-                            println!("\n{} Connection #{} error: {}", "✗".red(), id, e);
-                            print!("{} ", ">".bright_green().bold());
-                            io::stdout().flush().unwrap();
+                            let (description, _) = describe_read_error(&e);
+                            Event::error(Some(id), format!("Connection #{} error: {}", id, description)).print_over_prompt();
                             break;
                         }
                         None => {
@@ -224,7 +1362,391 @@ async fn create_connection(
         }
     });
 
-    Ok((id, tx, handle))
+    let conn = Connection {
+        id,
+        url: url.to_string(),
+        label: None,
+        tx,
+        received,
+        welcome: options.welcome_timeout.map(|timeout| (welcome_rx, timeout)),
+    };
+    Ok((conn, handle))
+}
+
+/// The verbose-mode line for a received frame. A close frame's payload is
+/// rebuilt from its code and reason, as it was on the wire
+fn frame_event(id: usize, message: &Message) -> Event {
+    let close_payload;
+    let (opcode, payload): (&'static str, &[u8]) = match message {
+        Message::Text(text) => ("text", text.as_bytes()),
+        Message::Binary(data) => ("binary", data),
+        Message::Ping(data) => ("ping", data),
+        Message::Pong(data) => ("pong", data),
+        Message::Close(frame) => {
+            close_payload = frame
+                .as_ref()
+                .map(|frame| {
+                    let mut payload = u16::from(frame.code).to_be_bytes().to_vec();
+                    payload.extend_from_slice(frame.reason.as_bytes());
+                    payload
+                })
+                .unwrap_or_default();
+            ("close", &close_payload)
+        }
+        Message::Frame(frame) => ("raw", frame.payload()),
+    };
+    Event::Frame {
+        id,
+        opcode,
+        len: payload.len(),
+        hex: to_hex(&payload[..payload.len().min(VERBOSE_HEX_BYTES)]),
+    }
+}
+
+/// `bytes` as space-separated lowercase hex pairs
+fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// TLS for `wss://` URLs, plain TCP for anything else. TLS configurations are
+/// built once and shared by every connection.
+fn connector_for(scheme: Option<&str>, insecure: bool) -> Connector {
+    static VERIFIED: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    static INSECURE: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+
+    if !scheme.is_some_and(|scheme| scheme.eq_ignore_ascii_case("wss")) {
+        return Connector::Plain;
+    }
+    let config = if insecure { &INSECURE } else { &VERIFIED };
+    Connector::Rustls(
+        config
+            .get_or_init(|| Arc::new(tls_config(insecure)))
+            .clone(),
+    )
+}
+
+/// rustls client settings: the Mozilla root store, or no verification at all
+fn tls_config(insecure: bool) -> ClientConfig {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default TLS versions");
+    if insecure {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+            .with_no_client_auth()
+    } else {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        builder.with_root_certificates(roots).with_no_client_auth()
+    }
+}
+
+/// Trusts any server certificate for `--insecure`; signatures are still
+/// checked so the handshake itself stays valid
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Retries `create_connection` with backoff until it succeeds or `timeout` passes
+async fn wait_for_server(
+    id: usize,
+    url: &str,
+    options: ConnectOptions,
+    timeout: Duration,
+) -> Option<(Connection, tokio::task::JoinHandle<()>)> {
+    let deadline = Instant::now() + timeout;
+    let mut backoff = Backoff::new(Duration::from_millis(250), Duration::from_secs(5));
+    let mut attempt = 1;
+    loop {
+        match create_connection(id, url, options).await {
+            Ok(connection) => return Some(connection),
+            Err(e) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return None;
+                }
+                let delay = backoff.next_delay().min(remaining);
+                Event::WaitingForServer {
+                    attempt,
+                    error: e.to_string(),
+                    retry_in: delay,
+                }
+                .print();
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Opens `count` connections with at most `concurrency` handshakes in flight,
+/// then prints how many succeeded, the distinct errors and the total time
+async fn load_test(
+    url: &str,
+    options: ConnectOptions,
+    first_id: usize,
+    count: usize,
+    concurrency: usize,
+    connections: &mut HashMap<usize, Connection>,
+) {
+    note(format!(
+        "Opening {} connections, {} at a time...",
+        count, concurrency
+    ));
+    // Handshakes overlap, so there is no single welcome to wait for
+    let options = ConnectOptions {
+        welcome_timeout: None,
+        ..options
+    };
+    let started = std::time::Instant::now();
+    let mut ids = first_id..first_id + count;
+    let mut pending = FuturesUnordered::new();
+    for id in ids.by_ref().take(concurrency) {
+        pending.push(create_connection(id, url, options));
+    }
+
+    let mut succeeded = 0;
+    let mut errors: BTreeMap<String, usize> = BTreeMap::new();
+    while let Some(result) = pending.next().await {
+        match result {
+            Ok((conn, handle)) => {
+                connections.insert(conn.id, conn);
+                tokio::spawn(handle);
+                succeeded += 1;
+            }
+            Err(e) => *errors.entry(e.to_string()).or_default() += 1,
+        }
+        if let Some(id) = ids.next() {
+            pending.push(create_connection(id, url, options));
+        }
+    }
+
+    Event::LoadTest {
+        succeeded,
+        failed: count - succeeded,
+        elapsed: started.elapsed(),
+        errors,
+    }
+    .print();
+}
+
+/// Sends `count` echo requests over `conn` as fast as its queue allows, then
+/// waits up to `BENCH_ECHO_TIMEOUT` for the echoes and prints the throughput
+/// and how many never came back. Only echoes of the tagged `bench <n>`
+/// payloads are counted, and they aren't printed while it runs.
+async fn bench(conn: &Connection, json: bool, count: usize) {
+    note(format!(
+        "Sending {} messages on connection #{}...",
+        count, conn.id
+    ));
+    conn.received.benching.store(true, Ordering::Relaxed);
+    let before = conn.received.bench_echoes.load(Ordering::Relaxed);
+    let started = Instant::now();
+
+    let mut sent = 0u64;
+    for n in 0..count {
+        let text = format!("{}{}", BENCH_TAG, n);
+        let frame = if json {
+            ClientMessage::Echo {
+                payload: serde_json::Value::String(text),
+            }
+            .to_json()
+        } else {
+            text
+        };
+        if !conn.queue(Message::Text(frame)).await {
+            break;
+        }
+        sent += 1;
+    }
+
+    let deadline = Instant::now() + BENCH_ECHO_TIMEOUT;
+    let echoed = loop {
+        let echoed = conn.received.bench_echoes.load(Ordering::Relaxed) - before;
+        if echoed >= sent || Instant::now() >= deadline || conn.tx.is_closed() {
+            break echoed.min(sent);
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    let elapsed = started.elapsed();
+    conn.received.benching.store(false, Ordering::Relaxed);
+
+    Event::Bench {
+        id: conn.id,
+        sent,
+        echoed,
+        elapsed,
+    }
+    .print();
+}
+
+/// Whether `text` echoes a `bench` payload: `Echo: bench 12` from a plain
+/// server, or a JSON echo frame whose payload is `bench 12`
+fn is_bench_echo(text: &str) -> bool {
+    let is_tagged = |text: &str| {
+        text.rsplit_once(BENCH_TAG)
+            .is_some_and(|(_, n)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+    };
+    match serde_json::from_str::<ServerMessage>(text) {
+        Ok(ServerMessage::Echo {
+            payload: serde_json::Value::String(payload),
+            ..
+        }) => is_tagged(&payload),
+        Ok(_) => false,
+        Err(_) => is_tagged(text),
+    }
+}
+
+/// Renders a JSON protocol frame for display, falling back to the raw text
+fn describe_server_message(text: &str) -> String {
+    match serde_json::from_str::<ServerMessage>(text) {
+        Ok(ServerMessage::Welcome { message, .. }) => message,
+        Ok(ServerMessage::Echo {
+            payload,
+            seq: Some(seq),
+            ts: Some(ts),
+        }) => format!("Echo[seq={} ts={}]: {}", seq, ts, payload),
+        Ok(ServerMessage::Echo { payload, .. }) => format!("Echo: {}", payload),
+        Ok(ServerMessage::Broadcast { from, payload }) => format!("[{}] {}", from, payload),
+        Ok(ServerMessage::Pong { ts }) => format!("Pong (ts={})", ts),
+        Ok(ServerMessage::Subscribed { topic }) => format!("Subscribed to {}", topic),
+        Ok(ServerMessage::Unsubscribed { topic }) => format!("Unsubscribed from {}", topic),
+        Ok(ServerMessage::Publish { topic, from, data }) => {
+            format!("[{}] {}: {}", topic, from, data)
+        }
+        Ok(ServerMessage::ServerStats {
+            active_connections,
+            connections_rejected,
+            messages_in_per_sec,
+            messages_out_per_sec,
+            ..
+        }) => format!(
+            "Server stats: {} active, {} rejected, {:.1} msg/s in, {:.1} msg/s out",
+            active_connections, connections_rejected, messages_in_per_sec, messages_out_per_sec
+        ),
+        Ok(ServerMessage::Error { code, message }) => {
+            format!("{} {:?}: {}", "Error".red(), code, message)
+        }
+        Err(_) => text.to_string(),
+    }
+}
+
+/// Pretty-prints `text` with colors if it is a JSON object or array, possibly
+/// after a plain prefix such as `Echo: `; `None` for anything else
+fn pretty_json(text: &str) -> Option<String> {
+    let start = text.find(['{', '['])?;
+    let value: serde_json::Value = serde_json::from_str(&text[start..]).ok()?;
+    let mut out = text[..start].to_string();
+    write_colored_json(&value, 0, &mut out);
+    Some(out)
+}
+
+/// Appends `value` to `out` in `serde_json`'s pretty layout, colored by type
+fn write_colored_json(value: &serde_json::Value, indent: usize, out: &mut String) {
+    use serde_json::Value;
+
+    let pad = |level: usize| "  ".repeat(level);
+    match value {
+        Value::Null => out.push_str(&"null".dimmed().to_string()),
+        Value::Bool(b) => out.push_str(&b.to_string().magenta().to_string()),
+        Value::Number(n) => out.push_str(&n.to_string().yellow().to_string()),
+        Value::String(_) => out.push_str(&value.to_string().green().to_string()),
+        Value::Array(items) if items.is_empty() => out.push_str("[]"),
+        Value::Array(items) => {
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(&pad(indent + 1));
+                write_colored_json(item, indent + 1, out);
+                out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&pad(indent));
+            out.push(']');
+        }
+        Value::Object(fields) if fields.is_empty() => out.push_str("{}"),
+        Value::Object(fields) => {
+            out.push_str("{\n");
+            for (i, (key, item)) in fields.iter().enumerate() {
+                let key = Value::String(key.clone()).to_string();
+                out.push_str(&format!("{}{}: ", pad(indent + 1), key.cyan()));
+                write_colored_json(item, indent + 1, out);
+                out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&pad(indent));
+            out.push('}');
+        }
+    }
+}
+
+/// Builds a message of exactly `size` bytes of random content
+fn random_payload(size: usize, kind: PayloadKind) -> Message {
+    match kind {
+        PayloadKind::Text => Message::Text(
+            (0..size)
+                .map(|_| rand::random_range(b' '..=b'~') as char)
+                .collect(),
+        ),
+        PayloadKind::Binary => {
+            let mut data = vec![0u8; size];
+            rand::fill(&mut data[..]);
+            Message::Binary(data)
+        }
+    }
+}
+
+/// Whether a command argument is a server URL rather than a number or id
+fn is_url(arg: &str) -> bool {
+    arg.starts_with("ws://") || arg.starts_with("wss://")
 }
 
 fn parse_command(input: &str) -> Result<Command, String> {
@@ -235,41 +1757,184 @@ fn parse_command(input: &str) -> Result<Command, String> {
 
     match parts[0].to_lowercase().as_str() {
         "connect" | "c" => {
-            if parts.len() == 1 {
-                Ok(Command::Connect)
-            } else if parts.len() == 2 {
-                let count = parts[1]
-                    .parse::<usize>()
-                    .map_err(|_| "Invalid number".to_string())?;
-                Ok(Command::ConnectMultiple(count))
-            } else {
-                Err("Usage: connect [count]".to_string())
+            // An optional URL comes first and overrides --server
+            let (url, rest) = match parts.get(1) {
+                Some(arg) if is_url(arg) => (Some(arg.to_string()), &parts[2..]),
+                _ => (None, &parts[1..]),
+            };
+            match rest {
+                [] => Ok(Command::Connect(url)),
+                [count] => {
+                    let count = count
+                        .parse::<usize>()
+                        .map_err(|_| "Invalid number".to_string())?;
+                    Ok(Command::ConnectMultiple(url, count))
+                }
+                _ => Err("Usage: connect [url] [count]".to_string()),
+            }
+        }
+        "connectmany" => {
+            let urls = &parts[1..];
+            if urls.is_empty() || urls.len() > MAX_CONNECT_BATCH {
+                return Err(format!(
+                    "Usage: connectmany <url1> <url2> ... (at most {} URLs)",
+                    MAX_CONNECT_BATCH
+                ));
+            }
+            if let Some(invalid) = urls.iter().find(|url| !is_url(url)) {
+                return Err(format!("Not a ws:// or wss:// URL: {}", invalid));
             }
+            Ok(Command::ConnectMany(
+                urls.iter().map(|url| url.to_string()).collect(),
+            ))
+        }
+        "loadtest" => {
+            if parts.len() != 3 {
+                return Err("Usage: loadtest <count> <concurrency>".to_string());
+            }
+            let count = parts[1]
+                .parse::<usize>()
+                .map_err(|_| "Invalid number".to_string())?;
+            let concurrency = parts[2]
+                .parse::<usize>()
+                .map_err(|_| "Invalid concurrency".to_string())?;
+            if count == 0 || count > MAX_LOADTEST_CONNECTIONS {
+                return Err(format!(
+                    "Count must be between 1 and {}",
+                    MAX_LOADTEST_CONNECTIONS
+                ));
+            }
+            if concurrency == 0 || concurrency > count {
+                return Err("Concurrency must be between 1 and the count".to_string());
+            }
+            Ok(Command::LoadTest(count, concurrency))
         }
         "close" => {
             if parts.len() == 1 {
-                Err("Usage: close <id> or close all".to_string())
+                Err("Usage: close <id> [code] [reason] or close all".to_string())
             } else if parts[1].to_lowercase() == "all" {
                 Ok(Command::CloseAll)
             } else {
                 let id = parts[1]
                     .parse::<usize>()
                     .map_err(|_| "Invalid connection ID".to_string())?;
-                Ok(Command::Close(id))
+                let Some(code) = parts.get(2) else {
+                    return Ok(Command::Close(id, None));
+                };
+                let code = code
+                    .parse::<u16>()
+                    .ok()
+                    .map(CloseCode::from)
+                    .filter(|code| code.is_allowed())
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid close code {}: use 1000-1003, 1007-1013 or 3000-4999",
+                            code
+                        )
+                    })?;
+                // Keep the reason's own spacing
+                let reason = skip_words(input, 3);
+                if reason.len() > MAX_CLOSE_REASON_BYTES {
+                    return Err(format!(
+                        "Close reason must be at most {} bytes",
+                        MAX_CLOSE_REASON_BYTES
+                    ));
+                }
+                Ok(Command::Close(
+                    id,
+                    Some(CloseFrame {
+                        code,
+                        reason: reason.to_string().into(),
+                    }),
+                ))
             }
         }
         "list" | "ls" => Ok(Command::List),
         "send" | "s" => {
-            if parts.len() < 3 {
-                Err("Usage: send <id> <message>".to_string())
+            if parts.len() < 2 {
+                return Err("Usage: send <id> [message]".to_string());
+            }
+            let id = parts[1]
+                .parse::<usize>()
+                .map_err(|_| "Invalid connection ID".to_string())?;
+            if parts.len() == 2 {
+                Ok(Command::SendMultiline(id))
             } else {
-                let id = parts[1]
-                    .parse::<usize>()
-                    .map_err(|_| "Invalid connection ID".to_string())?;
-                let message = parts[2..].join(" ");
-                Ok(Command::Send(id, message))
+                // Keep the message's own spacing
+                Ok(Command::Send(id, skip_words(input, 2).to_string()))
+            }
+        }
+        "sendrand" => {
+            let usage = "Usage: sendrand <id> <size_bytes> [text|binary]";
+            if parts.len() < 3 || parts.len() > 4 {
+                return Err(usage.to_string());
+            }
+            let id = parts[1]
+                .parse::<usize>()
+                .map_err(|_| "Invalid connection ID".to_string())?;
+            let size = parts[2]
+                .parse::<usize>()
+                .map_err(|_| "Invalid size".to_string())?;
+            if size == 0 || size > MAX_RANDOM_PAYLOAD_BYTES {
+                return Err(format!(
+                    "Size must be between 1 and {} bytes",
+                    MAX_RANDOM_PAYLOAD_BYTES
+                ));
+            }
+            let kind = match parts.get(3).map(|kind| kind.to_lowercase()).as_deref() {
+                None | Some("text") => PayloadKind::Text,
+                Some("binary") => PayloadKind::Binary,
+                Some(_) => return Err(usage.to_string()),
+            };
+            Ok(Command::SendRandom(id, size, kind))
+        }
+        "bench" => {
+            if parts.len() != 3 {
+                return Err("Usage: bench <id> <count>".to_string());
+            }
+            let id = parts[1]
+                .parse::<usize>()
+                .map_err(|_| "Invalid connection ID".to_string())?;
+            let count = parts[2]
+                .parse::<usize>()
+                .map_err(|_| "Invalid number".to_string())?;
+            if count == 0 || count > MAX_BENCH_MESSAGES {
+                return Err(format!(
+                    "Count must be between 1 and {}",
+                    MAX_BENCH_MESSAGES
+                ));
             }
+            Ok(Command::Bench(id, count))
         }
+        "label" => {
+            let id = parts
+                .get(1)
+                .ok_or_else(|| "Usage: label <id> [name]".to_string())?
+                .parse::<usize>()
+                .map_err(|_| "Invalid connection ID".to_string())?;
+            // Keep the label's own spacing
+            let label = skip_words(input, 2);
+            Ok(Command::Label(
+                id,
+                (!label.is_empty()).then(|| label.to_string()),
+            ))
+        }
+        "export" | "import" => {
+            let path = skip_words(input, 1);
+            if path.is_empty() {
+                return Err(format!("Usage: {} <file>", parts[0].to_lowercase()));
+            }
+            if parts[0].eq_ignore_ascii_case("export") {
+                Ok(Command::Export(path.to_string()))
+            } else {
+                Ok(Command::Import(path.to_string()))
+            }
+        }
+        "verbose" => match parts[1..] {
+            [setting] if setting.eq_ignore_ascii_case("on") => Ok(Command::Verbose(true)),
+            [setting] if setting.eq_ignore_ascii_case("off") => Ok(Command::Verbose(false)),
+            _ => Err("Usage: verbose on|off".to_string()),
+        },
         "help" | "h" => Ok(Command::Help),
         "quit" | "exit" | "q" => Ok(Command::Quit),
         _ => Err(format!(
@@ -279,24 +1944,49 @@ fn parse_command(input: &str) -> Result<Command, String> {
     }
 }
 
+/// The rest of `input` after its first `count` whitespace-separated words
+fn skip_words(input: &str, count: usize) -> &str {
+    let mut rest = input.trim_start();
+    for _ in 0..count {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
+    }
+    rest
+}
+
 fn print_help() {
     println!("\n{}", "Available Commands:".bright_yellow().bold());
     println!();
     println!(
         "  {}  {}  - Create a new WebSocket connection",
         "connect".bright_cyan(),
-        "[count]".dimmed()
+        "[url] [count]".dimmed()
     );
     println!(
         "  {}     {}  - Alias for connect",
         "c".bright_cyan(),
-        "[count]".dimmed()
+        "[url] [count]".dimmed()
+    );
+    println!(
+        "  {} {} - Open one connection to each URL",
+        "connectmany".bright_cyan(),
+        "<url> <url>...".dimmed()
+    );
+    println!(
+        "  {} {} - Open connections in parallel and report the results",
+        "loadtest".bright_cyan(),
+        "<count> <concurrency>".dimmed()
     );
     println!(
         "  {}    {}  - Close a connection (or 'all')",
         "close".bright_cyan(),
         "<id|all>".dimmed()
     );
+    println!(
+        "  {}    {} - Close with a close code and reason",
+        "close".bright_cyan(),
+        "<id> <code> [reason]".dimmed()
+    );
     println!(
         "  {}          - List all active connections",
         "list".bright_cyan()
@@ -312,6 +2002,41 @@ fn print_help() {
         "s".bright_cyan(),
         "<id> <message>".dimmed()
     );
+    println!(
+        "  {}      {}      - Type a multi-line message, end with a '.' line",
+        "send".bright_cyan(),
+        "<id>".dimmed()
+    );
+    println!(
+        "  {} {} - Send a random payload of the given size",
+        "sendrand".bright_cyan(),
+        "<id> <size> [text|binary]".dimmed()
+    );
+    println!(
+        "  {}    {} - Time the echoes of count messages",
+        "bench".bright_cyan(),
+        "<id> <count>".dimmed()
+    );
+    println!(
+        "  {}    {}    - Name a connection in 'list' (no name clears it)",
+        "label".bright_cyan(),
+        "<id> [name]".dimmed()
+    );
+    println!(
+        "  {}   {}      - Save the open connections' URLs and labels as JSON",
+        "export".bright_cyan(),
+        "<file>".dimmed()
+    );
+    println!(
+        "  {}   {}      - Re-open the connections saved by export, with new ids",
+        "import".bright_cyan(),
+        "<file>".dimmed()
+    );
+    println!(
+        "  {}  {}      - Print every received frame, pings and pongs included, in hex",
+        "verbose".bright_cyan(),
+        "<on|off>".dimmed()
+    );
     println!(
         "  {}          - Show this help message",
         "help".bright_cyan()
@@ -322,6 +2047,9 @@ fn print_help() {
     println!("  {}      - Alias for quit", "q".bright_cyan());
     println!();
     println!("{}", "Note:".bright_yellow().bold());
+    println!("  Use --json to speak the typed JSON protocol; 'send' then wraps messages as echo requests");
+    println!("  Use --pretty to pretty-print and color received JSON");
+    println!("  Use --import <file> to re-open the connections of an exported session on startup");
     println!("  Use --server or -s flag to specify a custom server URL:");
     println!(
         "  {} {}",
@@ -331,9 +2059,673 @@ fn print_help() {
     println!("\n{}", "Examples:".bright_yellow().bold());
     println!("  connect       - Create 1 connection");
     println!("  connect 5     - Create 5 connections");
+    println!("  connect ws://10.0.0.2:8080 3 - Create 3 connections to another server");
+    println!("  connectmany ws://node1:8080 ws://node2:8080 - One connection to each node");
+    println!("  loadtest 200 20 - Open 200 connections, 20 handshakes at a time");
     println!("  list          - Show all connections");
     println!("  send 1 hello  - Send 'hello' to connection #1");
+    println!("  sendrand 1 1048576 binary - Send 1 MiB of random bytes to connection #1");
+    println!("  bench 1 10000 - Measure echo throughput on connection #1");
     println!("  close 1       - Close connection #1");
+    println!("  close 1 4000 bye - Close connection #1 with code 4000 and reason 'bye'");
     println!("  close all     - Close all connections");
+    println!("  label 1 alice - Show connection #1 as 'alice' in list");
+    println!("  export soak.json - Save the open connections to soak.json");
+    println!("  import soak.json - Re-open the connections saved in soak.json");
+    println!("  verbose on    - Show the server's keep-alive pings as they arrive");
+    println!();
+    println!("Press Tab to complete command names and connection ids.");
     println!();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(values: &[usize]) -> BTreeSet<usize> {
+        values.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_complete_command_names() {
+        let (start, candidates) = complete_input("c", &ids(&[]));
+        assert_eq!(start, 0);
+        assert_eq!(candidates, vec!["connect", "connectmany", "close"]);
+
+        let (_, candidates) = complete_input("", &ids(&[]));
+        assert_eq!(candidates.len(), COMMAND_NAMES.len());
+    }
+
+    #[test]
+    fn test_complete_after_multibyte_whitespace() {
+        let open = ids(&[1, 2]);
+        let (start, candidates) = complete_input("send\u{3000}", &open);
+        assert_eq!(start, "send\u{3000}".len());
+        assert_eq!(candidates, vec!["1", "2"]);
+
+        let (start, candidates) = complete_input("send\u{3000}1\u{a0}", &open);
+        assert_eq!(start, "send\u{3000}1\u{a0}".len());
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn test_complete_connection_ids() {
+        let open = ids(&[1, 2, 12]);
+
+        let (start, candidates) = complete_input("send 1", &open);
+        assert_eq!(start, 5);
+        assert_eq!(candidates, vec!["1", "12"]);
+
+        let (_, candidates) = complete_input("close ", &open);
+        assert_eq!(candidates, vec!["all", "1", "2", "12"]);
+
+        // Only the first argument is an id
+        let (_, candidates) = complete_input("send 1 he", &open);
+        assert!(candidates.is_empty());
+        let (_, candidates) = complete_input("list ", &open);
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn test_parse_loadtest() {
+        assert!(matches!(
+            parse_command("loadtest 100 10"),
+            Ok(Command::LoadTest(100, 10))
+        ));
+        assert!(parse_command("loadtest 0 1").is_err());
+        assert!(parse_command(&format!("loadtest {} 1", MAX_LOADTEST_CONNECTIONS + 1)).is_err());
+        assert!(parse_command("loadtest 10 0").is_err());
+        assert!(parse_command("loadtest 10 11").is_err());
+        assert!(parse_command("loadtest 10").is_err());
+    }
+
+    #[test]
+    fn test_parse_connect_forms() {
+        assert!(matches!(
+            parse_command("connect"),
+            Ok(Command::Connect(None))
+        ));
+        assert!(matches!(
+            parse_command("c 5"),
+            Ok(Command::ConnectMultiple(None, 5))
+        ));
+        assert!(matches!(
+            parse_command("connect ws://10.0.0.2:8080"),
+            Ok(Command::Connect(Some(url))) if url == "ws://10.0.0.2:8080"
+        ));
+        assert!(matches!(
+            parse_command("connect wss://node1.example.com 3"),
+            Ok(Command::ConnectMultiple(Some(url), 3)) if url == "wss://node1.example.com"
+        ));
+        assert!(parse_command("connect example.com").is_err());
+        assert!(parse_command("connect ws://a 3 4").is_err());
+
+        assert!(matches!(
+            parse_command("connectmany ws://a:1 ws://b:2"),
+            Ok(Command::ConnectMany(urls)) if urls == ["ws://a:1", "ws://b:2"]
+        ));
+        assert!(parse_command("connectmany").is_err());
+        assert!(parse_command("connectmany ws://a:1 5").is_err());
+        let too_many = vec!["ws://a"; MAX_CONNECT_BATCH + 1].join(" ");
+        assert!(parse_command(&format!("connectmany {}", too_many)).is_err());
+    }
+
+    #[test]
+    fn test_parse_send_forms() {
+        assert!(matches!(
+            parse_command("send 1 hello   spaced  world"),
+            Ok(Command::Send(1, message)) if message == "hello   spaced  world"
+        ));
+        assert!(matches!(
+            parse_command("s 2 {\"a\": 1}"),
+            Ok(Command::Send(2, message)) if message == "{\"a\": 1}"
+        ));
+        assert!(matches!(
+            parse_command("send 3"),
+            Ok(Command::SendMultiline(3))
+        ));
+        assert!(parse_command("send").is_err());
+        assert!(parse_command("send x").is_err());
+    }
+
+    #[test]
+    fn test_pretty_json() {
+        colored::control::set_override(false);
+
+        let text = r#"{"a":[1,true,null],"b":{},"c":"x\"y"}"#;
+        let expected =
+            serde_json::to_string_pretty(&serde_json::from_str::<serde_json::Value>(text).unwrap())
+                .unwrap();
+        assert_eq!(pretty_json(text).unwrap(), expected);
+
+        // JSON after a plain prefix, as in the echo server's replies
+        assert_eq!(
+            pretty_json(r#"Echo: {"a":1}"#).unwrap(),
+            "Echo: {\n  \"a\": 1\n}"
+        );
+
+        assert_eq!(pretty_json("hello"), None);
+        assert_eq!(pretty_json("42"), None);
+        assert_eq!(pretty_json("Echo: {not json"), None);
+    }
+
+    #[tokio::test]
+    async fn test_connect_errors_are_classified() {
+        let options = ConnectOptions {
+            json: false,
+            pretty: false,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            insecure: false,
+            welcome_timeout: None,
+        };
+
+        // A server at its limit answers the upgrade with a bare 503
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            let _ = socket
+                .write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n")
+                .await;
+        });
+        let result = create_connection(1, &format!("ws://{}", addr), options).await;
+        assert!(matches!(result, Err(ConnectError::AtCapacity)));
+
+        // Nothing listens on a port that was just released
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let result = create_connection(1, &format!("ws://{}", addr), options).await;
+        assert!(matches!(result, Err(ConnectError::Refused)));
+
+        let result = create_connection(1, "http://example.com", options).await;
+        assert!(matches!(result, Err(ConnectError::Other(_))));
+    }
+
+    #[tokio::test]
+    async fn test_bench_counts_echoes() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+            // Every echo is followed by something unrelated to the bench
+            while let Some(Ok(message)) = ws.next().await {
+                if message.is_text()
+                    && (ws.send(message).await.is_err()
+                        || ws.send(Message::Text("news".into())).await.is_err())
+                {
+                    break;
+                }
+            }
+        });
+        let options = ConnectOptions {
+            json: false,
+            pretty: false,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            insecure: false,
+            welcome_timeout: None,
+        };
+        let (conn, handle) = create_connection(1, &format!("ws://{}", addr), options)
+            .await
+            .unwrap();
+        tokio::spawn(handle);
+
+        bench(&conn, false, 200).await;
+        assert_eq!(conn.received.bench_echoes.load(Ordering::Relaxed), 200);
+        assert!(!conn.received.benching.load(Ordering::Relaxed));
+        assert_eq!(conn.health(), Health::Active);
+    }
+
+    #[test]
+    fn test_connector_follows_the_scheme() {
+        assert!(matches!(connector_for(Some("ws"), false), Connector::Plain));
+        assert!(matches!(connector_for(None, true), Connector::Plain));
+
+        let rustls_config = |connector| match connector {
+            Connector::Rustls(config) => config,
+            _ => panic!("wss:// should use TLS"),
+        };
+        let verified = rustls_config(connector_for(Some("wss"), false));
+        let insecure = rustls_config(connector_for(Some("WSS"), true));
+        assert!(!Arc::ptr_eq(&verified, &insecure));
+        // Built once, then shared
+        assert!(Arc::ptr_eq(
+            &verified,
+            &rustls_config(connector_for(Some("wss"), false))
+        ));
+
+        let args = Args::try_parse_from(["client", "--insecure"]).unwrap();
+        assert!(args.insecure);
+    }
+
+    #[tokio::test]
+    async fn test_connection_queue_is_bounded() {
+        let (tx, mut rx) = mpsc::channel(2);
+        let conn = Connection {
+            id: 1,
+            url: DEFAULT_SERVER_URL.to_string(),
+            label: None,
+            tx,
+            received: Arc::default(),
+            welcome: None,
+        };
+
+        assert!(conn.queue(Message::Text("a".into())).await);
+        assert!(conn.queue(Message::Text("b".into())).await);
+        assert_eq!(conn.queue_depth(), 2);
+        assert!(!conn.queue(Message::Text("dropped".into())).await);
+
+        rx.try_recv().unwrap();
+        assert_eq!(conn.queue_depth(), 1);
+
+        assert!(Args::try_parse_from(["client", "--queue-capacity", "0"]).is_err());
+        let args = Args::try_parse_from(["client"]).unwrap();
+        assert_eq!(args.queue_capacity, DEFAULT_QUEUE_CAPACITY);
+    }
+
+    #[test]
+    fn test_parse_sendrand() {
+        assert!(matches!(
+            parse_command("sendrand 1 100"),
+            Ok(Command::SendRandom(1, 100, PayloadKind::Text))
+        ));
+        assert!(matches!(
+            parse_command("sendrand 2 5 BINARY"),
+            Ok(Command::SendRandom(2, 5, PayloadKind::Binary))
+        ));
+        assert!(parse_command("sendrand 1 0").is_err());
+        assert!(parse_command(&format!("sendrand 1 {}", MAX_RANDOM_PAYLOAD_BYTES + 1)).is_err());
+        assert!(parse_command("sendrand 1 10 json").is_err());
+        assert!(parse_command("sendrand 1").is_err());
+    }
+
+    #[test]
+    fn test_health_follows_the_last_frame() {
+        assert_eq!(Health::of(Duration::from_secs(1), false), Health::Active);
+        assert_eq!(Health::of(STALE_AFTER, false), Health::Active);
+        assert_eq!(
+            Health::of(STALE_AFTER + Duration::from_secs(1), false),
+            Health::Stale
+        );
+        assert_eq!(Health::of(Duration::ZERO, true), Health::Closed);
+    }
+
+    #[test]
+    fn test_parse_close_with_a_code() {
+        assert!(matches!(
+            parse_command("close 1"),
+            Ok(Command::Close(1, None))
+        ));
+        assert!(matches!(parse_command("close all"), Ok(Command::CloseAll)));
+        match parse_command("close 2 4000  going  away") {
+            Ok(Command::Close(2, Some(frame))) => {
+                assert_eq!(frame.code, CloseCode::Library(4000));
+                assert_eq!(frame.reason, "going  away");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_command("close 3 1001") {
+            Ok(Command::Close(3, Some(frame))) => {
+                assert_eq!(frame.code, CloseCode::Away);
+                assert_eq!(frame.reason, "");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        // Codes that must not appear on the wire, or aren't codes at all
+        for code in ["999", "1004", "1005", "1006", "1015", "2000", "5000", "abc"] {
+            assert!(
+                parse_command(&format!("close 1 {}", code)).is_err(),
+                "{}",
+                code
+            );
+        }
+        let long_reason = "x".repeat(MAX_CLOSE_REASON_BYTES + 1);
+        assert!(parse_command(&format!("close 1 1000 {}", long_reason)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_close_sends_the_given_frame() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let conn = Connection {
+            id: 1,
+            url: DEFAULT_SERVER_URL.to_string(),
+            label: None,
+            tx,
+            received: Arc::default(),
+            welcome: None,
+        };
+        let frame = CloseFrame {
+            code: CloseCode::Policy,
+            reason: "testing".into(),
+        };
+        conn.close(Some(frame.clone()));
+        assert_eq!(rx.recv().await, Some(Message::Close(Some(frame))));
+    }
+
+    #[test]
+    fn test_events_are_single_json_lines() {
+        let connected = Event::Connected {
+            id: 3,
+            url: "ws://127.0.0.1:8080".to_string(),
+            show_url: false,
+        };
+        assert_eq!(
+            connected.to_json(),
+            r#"{"event":"connected","id":3,"url":"ws://127.0.0.1:8080"}"#
+        );
+        assert!(connected.human().ends_with("Connection #3 established"));
+
+        let recv = Event::Recv {
+            id: 3,
+            text: "two\nlines".to_string(),
+        };
+        assert_eq!(
+            recv.to_json(),
+            r#"{"event":"recv","id":3,"text":"two\nlines"}"#
+        );
+        assert_eq!(
+            Event::not_found(2).to_json(),
+            r#"{"event":"error","id":2,"message":"Connection #2 not found"}"#
+        );
+        assert_eq!(
+            Event::error(None, "Unknown command: x").to_json(),
+            r#"{"event":"error","message":"Unknown command: x"}"#
+        );
+        assert_eq!(
+            Event::ServerClosed {
+                id: 1,
+                code: Some(4000),
+                reason: None,
+            }
+            .to_json(),
+            r#"{"event":"server_closed","id":1,"code":4000}"#
+        );
+        assert_eq!(
+            Event::Bench {
+                id: 1,
+                sent: 10,
+                echoed: 9,
+                elapsed: Duration::from_millis(1500),
+            }
+            .to_json(),
+            r#"{"event":"bench","id":1,"sent":10,"echoed":9,"elapsed_ms":1500}"#
+        );
+    }
+
+    #[test]
+    fn test_parse_verbose() {
+        assert!(matches!(
+            parse_command("verbose on"),
+            Ok(Command::Verbose(true))
+        ));
+        assert!(matches!(
+            parse_command("verbose OFF"),
+            Ok(Command::Verbose(false))
+        ));
+        assert!(parse_command("verbose").is_err());
+        assert!(parse_command("verbose maybe").is_err());
+        assert_eq!(
+            complete_input("verbose o", &ids(&[])),
+            (8, vec!["on".to_string(), "off".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_frame_events_show_control_payloads_in_hex() {
+        let ping = frame_event(2, &Message::Ping(vec![0x00, 0x2a, 0xff]));
+        assert_eq!(
+            ping.to_json(),
+            r#"{"event":"frame","id":2,"opcode":"ping","len":3,"hex":"00 2a ff"}"#
+        );
+        assert!(ping.human().contains("3 bytes: "));
+
+        let pong = frame_event(2, &Message::Pong(Vec::new()));
+        assert_eq!(
+            pong.to_json(),
+            r#"{"event":"frame","id":2,"opcode":"pong","len":0,"hex":""}"#
+        );
+
+        let close = frame_event(
+            2,
+            &Message::Close(Some(CloseFrame {
+                code: CloseCode::Normal,
+                reason: "ok".into(),
+            })),
+        );
+        assert!(matches!(
+            close,
+            Event::Frame { opcode: "close", len: 4, ref hex, .. } if hex == "03 e8 6f 6b"
+        ));
+
+        // Long payloads are cut in the dump but keep their full length
+        let big = frame_event(2, &Message::Binary(vec![0xab; 100]));
+        let Event::Frame { len, ref hex, .. } = big else {
+            panic!("expected a frame event");
+        };
+        assert_eq!(len, 100);
+        assert_eq!(hex.split(' ').count(), VERBOSE_HEX_BYTES);
+        assert!(big.human().ends_with(" (+36 bytes)"));
+    }
+
+    #[test]
+    fn test_list_event_carries_each_connection() {
+        let list = Event::Connections {
+            connections: vec![ConnectionStatus {
+                id: 1,
+                label: Some("alice".to_string()),
+                url: "ws://a".to_string(),
+                health: Health::Stale,
+                queue_depth: 0,
+                queue_capacity: 8,
+                idle_ms: 40_000,
+            }],
+        };
+        assert_eq!(
+            list.to_json(),
+            r#"{"event":"connections","connections":[{"id":1,"label":"alice","url":"ws://a","health":"stale","queue_depth":0,"queue_capacity":8,"idle_ms":40000}]}"#
+        );
+        assert!(list
+            .human()
+            .contains("Connection #1 [alice] to ws://a (queue 0/8, last frame 40s ago)"));
+        assert_eq!(
+            Event::Connections {
+                connections: Vec::new()
+            }
+            .human(),
+            "No active connections"
+        );
+    }
+
+    #[test]
+    fn test_parse_label_export_import() {
+        assert!(matches!(
+            parse_command("label 2 load  gen"),
+            Ok(Command::Label(2, Some(label))) if label == "load  gen"
+        ));
+        assert!(matches!(
+            parse_command("label 2"),
+            Ok(Command::Label(2, None))
+        ));
+        assert!(parse_command("label").is_err());
+        assert!(parse_command("label x name").is_err());
+        assert!(matches!(
+            parse_command("export /tmp/my session.json"),
+            Ok(Command::Export(path)) if path == "/tmp/my session.json"
+        ));
+        assert!(matches!(
+            parse_command("import soak.json"),
+            Ok(Command::Import(path)) if path == "soak.json"
+        ));
+        assert!(parse_command("export").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_then_import_reopens_the_connections() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+                    while let Some(Ok(_)) = ws.next().await {}
+                });
+            }
+        });
+        let options = ConnectOptions {
+            json: false,
+            pretty: false,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            insecure: false,
+            welcome_timeout: None,
+        };
+
+        let mut exported = HashMap::new();
+        for (id, label) in [(1, Some("alice")), (4, None)] {
+            let (mut conn, handle) = create_connection(id, &url, options).await.unwrap();
+            tokio::spawn(handle);
+            conn.label = label.map(str::to_string);
+            exported.insert(id, conn);
+        }
+        let path = std::env::temp_dir().join(format!(
+            "client-session-{}-{}.json",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        let session = Session::of(&exported);
+        session.save(&path).unwrap();
+        assert_eq!(Session::load(&path).unwrap(), session);
+        assert_eq!(
+            session.connections,
+            [
+                SavedConnection {
+                    url: url.clone(),
+                    label: Some("alice".to_string()),
+                },
+                SavedConnection {
+                    url: url.clone(),
+                    label: None,
+                },
+            ]
+        );
+
+        // Imported connections get fresh ids after the ones in use
+        let mut connections = HashMap::new();
+        let mut next_id = 7;
+        import_session(
+            path.to_str().unwrap(),
+            options,
+            &mut next_id,
+            &mut connections,
+        )
+        .await;
+        assert_eq!(next_id, 9);
+        assert_eq!(Session::of(&connections), session);
+        assert_eq!(connections[&7].label.as_deref(), Some("alice"));
+        assert!(connections[&8].label.is_none());
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(Session::load(&path).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wait_welcome_hands_over_the_first_message() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            // The first connection gets a late welcome, the second none
+            let mut welcome = Some("Connected to WebSocket server");
+            while let Ok((socket, _)) = listener.accept().await {
+                let welcome = welcome.take();
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+                    if let Some(text) = welcome {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        ws.send(Message::Text(text.into())).await.unwrap();
+                    }
+                    while let Some(Ok(_)) = ws.next().await {}
+                });
+            }
+        });
+        let options = ConnectOptions {
+            json: false,
+            pretty: false,
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            insecure: false,
+            welcome_timeout: Some(Duration::from_millis(300)),
+        };
+
+        let (mut conn, handle) = create_connection(1, &url, options).await.unwrap();
+        tokio::spawn(handle);
+        let (welcome, timeout) = conn.welcome.take().unwrap();
+        assert_eq!(timeout, Duration::from_millis(300));
+        assert_eq!(
+            welcome.await.unwrap(),
+            Event::Recv {
+                id: 1,
+                text: "Connected to WebSocket server".to_string(),
+            }
+        );
+
+        // Silence only holds `connect` up until the timeout
+        let (mut conn, handle) = create_connection(2, &url, options).await.unwrap();
+        tokio::spawn(handle);
+        let started = Instant::now();
+        conn.wait_for_welcome().await;
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(conn.welcome.is_none());
+
+        let options = ConnectOptions {
+            welcome_timeout: None,
+            ..options
+        };
+        let (conn, handle) = create_connection(3, &url, options).await.unwrap();
+        tokio::spawn(handle);
+        assert!(conn.welcome.is_none());
+    }
+
+    #[test]
+    fn test_is_bench_echo() {
+        assert!(is_bench_echo("Echo: bench 12"));
+        assert!(is_bench_echo(
+            r#"{"type":"echo","payload":"bench 3","seq":4}"#
+        ));
+        assert!(!is_bench_echo("bench"));
+        assert!(!is_bench_echo("Echo: bench 12!"));
+        assert!(!is_bench_echo(r#"{"type":"echo","payload":{"a":1}}"#));
+        assert!(!is_bench_echo(
+            r#"{"type":"broadcast","payload":"bench 1"}"#
+        ));
+    }
+
+    #[test]
+    fn test_parse_bench() {
+        assert!(matches!(
+            parse_command("bench 3 500"),
+            Ok(Command::Bench(3, 500))
+        ));
+        assert!(parse_command("bench 1 0").is_err());
+        assert!(parse_command(&format!("bench 1 {}", MAX_BENCH_MESSAGES + 1)).is_err());
+        assert!(parse_command("bench 1").is_err());
+        assert!(parse_command("bench x 10").is_err());
+    }
+
+    #[test]
+    fn test_random_payload_size_and_kind() {
+        match random_payload(1000, PayloadKind::Text) {
+            Message::Text(text) => {
+                assert_eq!(text.len(), 1000);
+                assert!(text.bytes().all(|b| (b' '..=b'~').contains(&b)));
+            }
+            other => panic!("expected text, got {:?}", other),
+        }
+        match random_payload(10, PayloadKind::Binary) {
+            Message::Binary(data) => assert_eq!(data.len(), 10),
+            other => panic!("expected binary, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Output formats for the server's log lines.
+//!
+//! `text` is tracing-subscriber's usual human-readable line, prefixed with
+//! the spans the event happened in (`conn{peer=127.0.0.1:5000 id=3}:`).
+//! `json` writes one object per line for log pipelines: `timestamp`, `level`,
+//! `target` and `message`, the event's own fields such as `event` and
+//! `active` at the top level, and the innermost span under `span` with its
+//! `name`, `id` and `peer`. Numbers and booleans stay numbers and booleans.
+
+use std::fmt;
+use std::str::FromStr;
+use tracing::Subscriber;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}' (use text or json)", s)),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        })
+    }
+}
+
+/// `base` for everything, with the `RUST_LOG` directives applied on top.
+/// Directives that don't parse are skipped rather than failing startup
+pub fn log_filter(base: LevelFilter, rust_log: Option<&str>) -> EnvFilter {
+    match rust_log
+        .map(str::trim)
+        .filter(|directives| !directives.is_empty())
+    {
+        Some(directives) => EnvFilter::new(format!("{},{}", base, directives)),
+        None => EnvFilter::new(base.to_string()),
+    }
+}
+
+/// A subscriber writing `format` lines to `writer`
+pub fn subscriber<W>(
+    format: LogFormat,
+    filter: EnvFilter,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(
+            builder
+                .json()
+                .flatten_event(true)
+                .with_current_span(true)
+                .with_span_list(false)
+                .finish(),
+        ),
+    }
+}
+
+/// Installs the global subscriber writing to stderr; records from crates
+/// still on `log` are forwarded into it
+pub fn init(format: LogFormat, filter: EnvFilter) {
+    let ansi = format == LogFormat::Text && std::io::IsTerminal::is_terminal(&std::io::stderr());
+    // Only fails when a subscriber is already installed
+    let _ = subscriber(format, filter, std::io::stderr, ansi).try_init();
+}
+
+/// Collects what a subscriber writes, for tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Captured {
+    pub fn output(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl<'w> MakeWriter<'w> for Captured {
+    type Writer = Captured;
+
+    fn make_writer(&'w self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::net::SocketAddr;
+    use tracing::{debug, info, info_span, warn};
+
+    #[test]
+    fn test_parse_log_format() {
+        assert_eq!("text".parse(), Ok(LogFormat::Text));
+        assert_eq!("JSON".parse(), Ok(LogFormat::Json));
+        assert!("logfmt".parse::<LogFormat>().is_err());
+        assert_eq!(LogFormat::default(), LogFormat::Text);
+    }
+
+    /// Runs `f` with a subscriber writing `format` into the returned text
+    fn capture(format: LogFormat, filter: EnvFilter, f: impl FnOnce()) -> String {
+        let captured = Captured::default();
+        let subscriber = subscriber(format, filter, captured.clone(), false);
+        tracing::subscriber::with_default(subscriber, f);
+        captured.output()
+    }
+
+    fn connection_events() {
+        let peer: SocketAddr = "127.0.0.1:4242".parse().unwrap();
+        let span = info_span!("conn", id = tracing::field::Empty, peer = %peer);
+        let _guard = span.enter();
+        span.record("id", 7u64);
+        info!(
+            event = "connection_open",
+            active = 2u32,
+            "Connection opened"
+        );
+        debug!(event = "message_received", bytes = 5usize, "Received");
+        warn!(
+            event = "connection_close",
+            clean = true,
+            "Connection closed"
+        );
+    }
+
+    #[test]
+    fn test_json_lines_carry_typed_fields_and_span() {
+        let output = capture(
+            LogFormat::Json,
+            log_filter(LevelFilter::INFO, None),
+            connection_events,
+        );
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        // The debug event is filtered out
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0]["timestamp"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["target"], "server::log_format::tests");
+        assert_eq!(lines[0]["message"], "Connection opened");
+        assert_eq!(lines[0]["event"], "connection_open");
+        assert_eq!(lines[0]["active"], 2);
+        assert_eq!(lines[0]["span"]["name"], "conn");
+        assert_eq!(lines[0]["span"]["id"], 7);
+        assert_eq!(lines[0]["span"]["peer"], "127.0.0.1:4242");
+        assert_eq!(lines[1]["level"], "WARN");
+        assert_eq!(lines[1]["event"], "connection_close");
+        assert_eq!(lines[1]["clean"], true);
+        assert_eq!(lines[1]["span"]["id"], 7);
+    }
+
+    #[test]
+    fn test_text_lines_are_prefixed_with_span_fields() {
+        let output = capture(
+            LogFormat::Text,
+            log_filter(LevelFilter::INFO, None),
+            connection_events,
+        );
+        let first = output.lines().next().unwrap();
+        assert!(
+            first.contains("conn{peer=127.0.0.1:4242 id=7}:"),
+            "{}",
+            first
+        );
+        assert!(first.contains("Connection opened"), "{}", first);
+        assert!(first.contains("active=2"), "{}", first);
+    }
+
+    #[test]
+    fn test_rust_log_directives_apply_on_top_of_base() {
+        let debug_here = log_filter(LevelFilter::WARN, Some("server::log_format=debug"));
+        let output = capture(LogFormat::Json, debug_here, connection_events);
+        assert_eq!(output.lines().count(), 3);
+
+        let quieter = log_filter(LevelFilter::INFO, Some("warn"));
+        let output = capture(LogFormat::Json, quieter, connection_events);
+        assert_eq!(output.lines().count(), 1);
+
+        let output = capture(
+            LogFormat::Json,
+            log_filter(LevelFilter::WARN, Some("  ")),
+            connection_events,
+        );
+        assert_eq!(output.lines().count(), 1);
+    }
+}
@@ -0,0 +1,349 @@
+//! Append-only JSON lines record of the messages connections exchange.
+//!
+//! Records are handed to a dedicated writer thread through a bounded channel,
+//! so a slow disk costs dropped records rather than stalled connections. The
+//! file is rotated by size: `messages.jsonl` becomes `messages.jsonl.1`, the
+//! previous `.1` becomes `.2` and so on, and the oldest kept file is removed.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{error, warn};
+
+/// Size at which the file is rotated when `persist_max_bytes` isn't set
+pub const DEFAULT_PERSIST_MAX_BYTES: u64 = 10 * 1024 * 1024;
+/// Rotated files kept next to the live one
+pub const DEFAULT_PERSIST_KEEP_FILES: usize = 3;
+/// Records that may wait for the writer before new ones are dropped
+const PERSIST_QUEUE_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Sent by the client
+    In,
+    /// Sent to the client
+    Out,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadKind {
+    Text,
+    /// `payload` holds the bytes as lowercase hex
+    Binary,
+}
+
+/// One line of the file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersistedMessage {
+    /// Unix milliseconds
+    pub ts: u64,
+    /// Connection id
+    pub conn: u64,
+    pub direction: Direction,
+    /// Display name of the connection at that time
+    pub from: String,
+    pub kind: PayloadKind,
+    pub payload: String,
+}
+
+impl PersistedMessage {
+    /// `None` for control frames, which aren't persisted
+    pub fn new(
+        ts: u64,
+        conn: u64,
+        direction: Direction,
+        from: String,
+        message: &Message,
+    ) -> Option<Self> {
+        let (kind, payload) = match message {
+            Message::Text(text) => (PayloadKind::Text, text.clone()),
+            Message::Binary(data) => (
+                PayloadKind::Binary,
+                data.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            ts,
+            conn,
+            direction,
+            from,
+            kind,
+            payload,
+        })
+    }
+}
+
+/// `path` with `.n` appended, the name of the `n`th most recent rotated file
+pub fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Handle to the writer thread; dropping it lets the thread finish
+pub struct MessageLog {
+    records: mpsc::Sender<PersistedMessage>,
+    dropped: AtomicU64,
+}
+
+impl MessageLog {
+    /// Opens `path` for appending and starts the writer thread. The file is
+    /// rotated before a record would take it past `max_bytes`.
+    pub fn open(path: PathBuf, max_bytes: u64, keep_files: usize) -> io::Result<Self> {
+        let writer = LogWriter::open(path, max_bytes, keep_files)?;
+        let (records, receiver) = mpsc::channel(PERSIST_QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("message-log".to_string())
+            .spawn(move || writer.run(receiver))?;
+        Ok(Self {
+            records,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Queues `record` without waiting; it is dropped if the writer is behind
+    pub fn append(&self, record: PersistedMessage) {
+        if self.records.try_send(record).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped.is_power_of_two() {
+                warn!(
+                    "Message log writer is behind - {} records dropped so far",
+                    dropped
+                );
+            }
+        }
+    }
+
+    /// Records dropped because the writer fell behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+struct LogWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    keep_files: usize,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl LogWriter {
+    fn open(path: PathBuf, max_bytes: u64, keep_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            keep_files,
+            file: BufWriter::new(file),
+            size,
+        })
+    }
+
+    fn run(mut self, mut receiver: mpsc::Receiver<PersistedMessage>) {
+        while let Some(record) = receiver.blocking_recv() {
+            // Write whatever else is already queued before flushing
+            let mut next = Some(record);
+            while let Some(record) = next {
+                if let Err(e) = self.write(&record) {
+                    error!("Failed to write to {}: {}", self.path.display(), e);
+                }
+                next = receiver.try_recv().ok();
+            }
+            if let Err(e) = self.file.flush() {
+                error!("Failed to write to {}: {}", self.path.display(), e);
+            }
+        }
+    }
+
+    fn write(&mut self, record: &PersistedMessage) -> io::Result<()> {
+        let mut line = serde_json::to_string(record).expect("records always serialize");
+        line.push('\n');
+        let len = line.len() as u64;
+        if self.size > 0 && self.size + len > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep_files > 0 {
+            for n in (1..self.keep_files).rev() {
+                let from = rotated_path(&self.path, n);
+                if from.exists() {
+                    std::fs::rename(&from, rotated_path(&self.path, n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        // Without kept files the live file simply starts over
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Every record in the live file and its rotated predecessors, oldest first.
+/// Missing files are skipped, as are lines that don't parse.
+pub fn read_persisted(path: &Path, keep_files: usize) -> Vec<PersistedMessage> {
+    let mut files: Vec<PathBuf> = (1..=keep_files)
+        .rev()
+        .map(|n| rotated_path(path, n))
+        .collect();
+    files.push(path.to_path_buf());
+
+    let mut records = Vec::new();
+    for file in files {
+        let Ok(opened) = File::open(&file) else {
+            continue;
+        };
+        for line in BufReader::new(opened).lines() {
+            let Ok(line) = line else { break };
+            match serde_json::from_str(&line) {
+                Ok(record) => records.push(record),
+                Err(e) => warn!("Skipping unreadable line in {}: {}", file.display(), e),
+            }
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn temp_log_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "message-log-{}-{}-{}",
+            name,
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("messages.jsonl")
+    }
+
+    fn text_record(conn: u64, text: &str) -> PersistedMessage {
+        PersistedMessage::new(
+            1_700_000_000_000,
+            conn,
+            Direction::In,
+            format!("user{}", conn),
+            &Message::Text(text.to_string()),
+        )
+        .unwrap()
+    }
+
+    /// Waits until the record with payload `last` is on disk
+    fn wait_for_records(path: &Path, keep_files: usize, last: &str) -> Vec<PersistedMessage> {
+        for _ in 0..100 {
+            let records = read_persisted(path, keep_files);
+            if records.last().is_some_and(|record| record.payload == last) {
+                return records;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        panic!("expected {:?} on disk", last);
+    }
+
+    #[test]
+    fn test_records_are_json_lines() {
+        let path = temp_log_path("lines");
+        let log = MessageLog::open(path.clone(), DEFAULT_PERSIST_MAX_BYTES, 1).unwrap();
+        log.append(text_record(1, "hello"));
+        log.append(
+            PersistedMessage::new(
+                5,
+                2,
+                Direction::Out,
+                "user2".to_string(),
+                &Message::Binary(vec![0x00, 0xab]),
+            )
+            .unwrap(),
+        );
+        wait_for_records(&path, 1, "00ab");
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"ts":1700000000000,"conn":1,"direction":"in","from":"user1","kind":"text","payload":"hello"}"#,
+                r#"{"ts":5,"conn":2,"direction":"out","from":"user2","kind":"binary","payload":"00ab"}"#,
+            ]
+        );
+        assert_eq!(log.dropped(), 0);
+        assert!(
+            PersistedMessage::new(0, 1, Direction::In, String::new(), &Message::Ping(vec![]))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_rotation_keeps_the_newest_files() {
+        let path = temp_log_path("rotate");
+        let line_len = serde_json::to_string(&text_record(1, "message 0"))
+            .unwrap()
+            .len() as u64
+            + 1;
+        // Two records per file, two rotated files kept
+        let log = MessageLog::open(path.clone(), line_len * 2, 2).unwrap();
+        for n in 0..7 {
+            log.append(text_record(1, &format!("message {}", n)));
+        }
+        let records = wait_for_records(&path, 2, "message 6");
+
+        let payloads: Vec<&str> = records.iter().map(|r| r.payload.as_str()).collect();
+        assert_eq!(
+            payloads,
+            [
+                "message 2",
+                "message 3",
+                "message 4",
+                "message 5",
+                "message 6"
+            ]
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        assert_eq!(
+            std::fs::read_to_string(rotated_path(&path, 2))
+                .unwrap()
+                .lines()
+                .count(),
+            2
+        );
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn test_reopening_appends() {
+        let path = temp_log_path("reopen");
+        let first = MessageLog::open(path.clone(), DEFAULT_PERSIST_MAX_BYTES, 1).unwrap();
+        first.append(text_record(1, "before restart"));
+        wait_for_records(&path, 1, "before restart");
+        drop(first);
+
+        let second = MessageLog::open(path.clone(), DEFAULT_PERSIST_MAX_BYTES, 1).unwrap();
+        second.append(text_record(2, "after restart"));
+        let records = wait_for_records(&path, 1, "after restart");
+        assert_eq!(records[0].payload, "before restart");
+        assert_eq!(records[1].payload, "after restart");
+    }
+}
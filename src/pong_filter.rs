@@ -0,0 +1,249 @@
+//! A transport wrapper that can keep the server's pongs off the wire.
+//!
+//! tungstenite queues a pong for every ping it reads and writes it on its own,
+//! so not answering a ping in the read loop isn't enough to withhold it. Once
+//! `drop_pongs` is called, after the HTTP handshake, `PongFilter` follows the
+//! frame boundaries of what the server writes and discards pong frames;
+//! everything else passes through byte for byte. Reads are never touched.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const OPCODE_PONG: u8 = 0xA;
+/// 2 bytes, an 8-byte extended length and a 4-byte mask key
+const MAX_HEADER_LEN: usize = 14;
+
+#[derive(Debug)]
+enum Scan {
+    Header {
+        buf: [u8; MAX_HEADER_LEN],
+        have: usize,
+    },
+    Payload {
+        remaining: u64,
+        keep: bool,
+    },
+}
+
+impl Scan {
+    fn header() -> Self {
+        Scan::Header {
+            buf: [0; MAX_HEADER_LEN],
+            have: 0,
+        }
+    }
+}
+
+/// Length of the header starting with `first_two`
+fn header_len(first_two: [u8; 2]) -> usize {
+    let extended = match first_two[1] & 0x7F {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    };
+    let mask = if first_two[1] & 0x80 != 0 { 4 } else { 0 };
+    2 + extended + mask
+}
+
+/// Payload length from a complete header
+fn payload_len(header: &[u8]) -> u64 {
+    match header[1] & 0x7F {
+        126 => u16::from_be_bytes([header[2], header[3]]) as u64,
+        127 => u64::from_be_bytes(header[2..10].try_into().unwrap()),
+        len => len as u64,
+    }
+}
+
+pub struct PongFilter<S> {
+    inner: S,
+    drop_pongs: bool,
+    scan: Scan,
+    /// Bytes of kept frames not yet written to `inner`
+    pending: Vec<u8>,
+}
+
+impl<S> PongFilter<S> {
+    /// Passes everything through until `drop_pongs` is called
+    pub fn new(inner: S) -> Self {
+        PongFilter {
+            inner,
+            drop_pongs: false,
+            scan: Scan::header(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Starts discarding pongs. Everything written from here on has to be
+    /// WebSocket frames, so call it once the handshake response is out
+    pub fn drop_pongs(&mut self) {
+        self.drop_pongs = true;
+    }
+
+    /// Moves the bytes of `buf` that belong to kept frames into `pending`
+    fn scan(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            match &mut self.scan {
+                Scan::Header { buf: header, have } => {
+                    let needed = if *have < 2 {
+                        2
+                    } else {
+                        header_len([header[0], header[1]])
+                    };
+                    let take = (needed - *have).min(buf.len());
+                    header[*have..*have + take].copy_from_slice(&buf[..take]);
+                    *have += take;
+                    buf = &buf[take..];
+                    if *have < 2 || *have < header_len([header[0], header[1]]) {
+                        continue;
+                    }
+                    let header = &header[..*have];
+                    let keep = header[0] & 0x0F != OPCODE_PONG;
+                    if keep {
+                        self.pending.extend_from_slice(header);
+                    }
+                    let remaining = payload_len(header);
+                    self.scan = if remaining == 0 {
+                        Scan::header()
+                    } else {
+                        Scan::Payload { remaining, keep }
+                    };
+                }
+                Scan::Payload { remaining, keep } => {
+                    let take = (*remaining).min(buf.len() as u64) as usize;
+                    if *keep {
+                        self.pending.extend_from_slice(&buf[..take]);
+                    }
+                    *remaining -= take as u64;
+                    buf = &buf[take..];
+                    if *remaining == 0 {
+                        self.scan = Scan::header();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> PongFilter<S> {
+    /// Writes out `pending`
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending.drain(..written);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for PongFilter<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for PongFilter<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if !this.drop_pongs {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+        ready!(this.poll_drain(cx))?;
+        this.scan(buf);
+        // Whatever doesn't fit now goes out with the next write or flush
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::{Data, OpCode};
+    use tokio_tungstenite::tungstenite::protocol::frame::Frame;
+
+    fn data_frame(payload: Vec<u8>, data: Data) -> Frame {
+        Frame::message(payload, OpCode::Data(data), true)
+    }
+
+    fn frame_bytes(frames: &[Frame]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for frame in frames {
+            frame.clone().format(&mut bytes).unwrap();
+        }
+        bytes
+    }
+
+    async fn filtered(bytes: &[u8], chunk: usize, drop_pongs: bool) -> Vec<u8> {
+        let mut filter = PongFilter::new(Vec::new());
+        if drop_pongs {
+            filter.drop_pongs();
+        }
+        for piece in bytes.chunks(chunk) {
+            filter.write_all(piece).await.unwrap();
+        }
+        filter.flush().await.unwrap();
+        filter.inner
+    }
+
+    #[tokio::test]
+    async fn test_drops_pongs_and_keeps_everything_else() {
+        let text = data_frame(b"hello".to_vec(), Data::Text);
+        // Past 125 and 65535 bytes the 2- and 8-byte extended lengths kick in
+        let medium = data_frame(vec![7; 300], Data::Binary);
+        let large = data_frame(vec![9; 70_000], Data::Binary);
+        let all = frame_bytes(&[
+            Frame::pong(b"first".to_vec()),
+            text.clone(),
+            Frame::pong(Vec::new()),
+            medium.clone(),
+            Frame::pong(vec![1; 125]),
+            Frame::ping(b"nonce".to_vec()),
+            large.clone(),
+            Frame::close(None),
+        ]);
+        let expected = frame_bytes(&[
+            text,
+            medium,
+            Frame::ping(b"nonce".to_vec()),
+            large,
+            Frame::close(None),
+        ]);
+        for chunk in [1, 3, 64, all.len()] {
+            assert_eq!(
+                filtered(&all, chunk, true).await,
+                expected,
+                "chunk {}",
+                chunk
+            );
+        }
+        assert_eq!(filtered(&all, 5, false).await, all);
+    }
+}
@@ -0,0 +1,336 @@
+//! JSON message envelope shared by the server and the client.
+//!
+//! Every frame is a JSON object with a `type` tag, e.g.
+//! `{"type":"echo","payload":"hello"}`. The envelope is used when the client
+//! negotiates the `json` subprotocol or the server runs with `json_protocol`.
+//!
+//! Topic frames are the exception: they carry an `action` tag instead, e.g.
+//! `{"action":"subscribe","topic":"news"}`, and are understood on every
+//! connection, plain text included.
+
+// Each binary only uses part of the protocol
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Subprotocol name that selects the JSON envelope
+pub const JSON_SUBPROTOCOL: &str = "json";
+
+/// Frames sent by clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Ask the server to send `payload` back
+    Echo { payload: Value },
+    /// Deliver `payload` to every other connection
+    Broadcast { payload: Value },
+    /// Application-level ping; answered with a `pong` carrying the same `ts`
+    Ping { ts: u64 },
+}
+
+/// Topic frames, sent by clients on any connection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum TopicAction {
+    /// Receive what is published to `topic` until unsubscribing; answered
+    /// with `subscribed`
+    Subscribe { topic: String },
+    /// Stop receiving `topic`; answered with `unsubscribed`
+    Unsubscribe { topic: String },
+    /// Deliver `data` to every connection subscribed to `topic`, the
+    /// publisher included if it is subscribed
+    Publish { topic: String, data: Value },
+}
+
+/// Frames sent by the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// `session` is only present when the server runs with `session_resume_secs`
+    Welcome {
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session: Option<String>,
+    },
+    /// `seq`/`ts` are only present when the server runs with `echo_metadata`
+    Echo {
+        payload: Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ts: Option<u64>,
+    },
+    Broadcast {
+        from: String,
+        payload: Value,
+    },
+    Pong {
+        ts: u64,
+    },
+    Subscribed {
+        topic: String,
+    },
+    Unsubscribed {
+        topic: String,
+    },
+    /// Published to a topic the connection subscribed to
+    Publish {
+        topic: String,
+        from: String,
+        data: Value,
+    },
+    /// Pushed every `stats_interval_secs` to connections that sent `/admin`;
+    /// the rates are per second over the last interval
+    ServerStats {
+        active_connections: u32,
+        uptime_secs: u64,
+        connections_accepted: u64,
+        connections_rejected: u64,
+        interval_secs: u64,
+        messages_in_per_sec: f64,
+        messages_out_per_sec: f64,
+        bytes_in_per_sec: f64,
+        bytes_out_per_sec: f64,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+/// Error codes carried by `ServerMessage::Error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The frame was not valid JSON
+    MalformedJson,
+    /// The frame was JSON but not an object with a string `type` field
+    MissingType,
+    /// The `type` field named a message the server does not know
+    UnknownType,
+    /// The `type` was known but the remaining fields did not match it
+    InvalidMessage,
+}
+
+impl ServerMessage {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        ServerMessage::Error {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("server messages always serialize")
+    }
+}
+
+impl ClientMessage {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("client messages always serialize")
+    }
+}
+
+impl TopicAction {
+    pub fn topic(&self) -> &str {
+        match self {
+            TopicAction::Subscribe { topic }
+            | TopicAction::Unsubscribe { topic }
+            | TopicAction::Publish { topic, .. } => topic,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("topic actions always serialize")
+    }
+
+    /// The topic frame in `text`, or `None` for anything else, which the
+    /// server then treats as ordinary text
+    pub fn decode(text: &str) -> Option<Self> {
+        if !text.trim_start().starts_with('{') {
+            return None;
+        }
+        serde_json::from_str(text).ok()
+    }
+}
+
+const CLIENT_MESSAGE_TYPES: &[&str] = &["echo", "broadcast", "ping"];
+
+/// Decodes a client frame, or describes why it was rejected
+pub fn decode_client_message(text: &str) -> Result<ClientMessage, ServerMessage> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|e| ServerMessage::error(ErrorCode::MalformedJson, e.to_string()))?;
+
+    let message_type = match value.get("type").and_then(Value::as_str) {
+        Some(message_type) => message_type.to_string(),
+        None => {
+            return Err(ServerMessage::error(
+                ErrorCode::MissingType,
+                "expected an object with a string \"type\" field",
+            ))
+        }
+    };
+    if !CLIENT_MESSAGE_TYPES.contains(&message_type.as_str()) {
+        return Err(ServerMessage::error(
+            ErrorCode::UnknownType,
+            format!("unknown message type '{}'", message_type),
+        ));
+    }
+
+    serde_json::from_value(value)
+        .map_err(|e| ServerMessage::error(ErrorCode::InvalidMessage, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_client_message_round_trip() {
+        let messages = vec![
+            ClientMessage::Echo {
+                payload: json!({"a": 1}),
+            },
+            ClientMessage::Broadcast {
+                payload: json!("hi"),
+            },
+            ClientMessage::Ping { ts: 42 },
+        ];
+        for message in messages {
+            let decoded = decode_client_message(&message.to_json()).unwrap();
+            assert_eq!(decoded, message);
+        }
+    }
+
+    #[test]
+    fn test_topic_action_decoding() {
+        let actions = vec![
+            TopicAction::Subscribe {
+                topic: "news".to_string(),
+            },
+            TopicAction::Unsubscribe {
+                topic: "news".to_string(),
+            },
+            TopicAction::Publish {
+                topic: "news".to_string(),
+                data: json!({"headline": "x"}),
+            },
+        ];
+        for action in actions {
+            assert_eq!(TopicAction::decode(&action.to_json()), Some(action));
+        }
+        assert_eq!(
+            TopicAction::decode(r#"{"action":"subscribe","topic":"news"}"#),
+            Some(TopicAction::Subscribe {
+                topic: "news".to_string()
+            })
+        );
+
+        // Anything else is left to the echo
+        for text in [
+            "hello",
+            "{not json",
+            r#"{"action":"dance","topic":"news"}"#,
+            r#"{"action":"publish","topic":"news"}"#,
+            r#"{"type":"echo","payload":"x"}"#,
+        ] {
+            assert_eq!(TopicAction::decode(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_server_message_round_trip() {
+        let messages = vec![
+            ServerMessage::Welcome {
+                message: "hello".to_string(),
+                session: None,
+            },
+            ServerMessage::Welcome {
+                message: "hello".to_string(),
+                session: Some("00ff".to_string()),
+            },
+            ServerMessage::ServerStats {
+                active_connections: 3,
+                uptime_secs: 60,
+                connections_accepted: 10,
+                connections_rejected: 1,
+                interval_secs: 5,
+                messages_in_per_sec: 2.5,
+                messages_out_per_sec: 3.0,
+                bytes_in_per_sec: 120.0,
+                bytes_out_per_sec: 140.5,
+            },
+            ServerMessage::Echo {
+                payload: json!([1, 2]),
+                seq: None,
+                ts: None,
+            },
+            ServerMessage::Echo {
+                payload: json!("x"),
+                seq: Some(3),
+                ts: Some(1_700_000_000_000),
+            },
+            ServerMessage::Broadcast {
+                from: "alice".to_string(),
+                payload: json!(null),
+            },
+            ServerMessage::Pong { ts: 7 },
+            ServerMessage::Subscribed {
+                topic: "news".to_string(),
+            },
+            ServerMessage::Publish {
+                topic: "news".to_string(),
+                from: "#1".to_string(),
+                data: json!(1),
+            },
+            ServerMessage::error(ErrorCode::UnknownType, "nope"),
+        ];
+        for message in messages {
+            let decoded: ServerMessage = serde_json::from_str(&message.to_json()).unwrap();
+            assert_eq!(decoded, message);
+        }
+    }
+
+    #[test]
+    fn test_wire_format() {
+        assert_eq!(
+            ClientMessage::Echo {
+                payload: json!("x")
+            }
+            .to_json(),
+            r#"{"type":"echo","payload":"x"}"#
+        );
+        assert_eq!(
+            ServerMessage::Echo {
+                payload: json!("x"),
+                seq: None,
+                ts: None
+            }
+            .to_json(),
+            r#"{"type":"echo","payload":"x"}"#
+        );
+        assert_eq!(
+            ServerMessage::error(ErrorCode::MalformedJson, "bad").to_json(),
+            r#"{"type":"error","code":"malformed_json","message":"bad"}"#
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let code_of = |text: &str| match decode_client_message(text) {
+            Err(ServerMessage::Error { code, .. }) => code,
+            other => panic!("expected error, got {:?}", other),
+        };
+
+        assert_eq!(code_of("not json"), ErrorCode::MalformedJson);
+        assert_eq!(code_of("[1, 2]"), ErrorCode::MissingType);
+        assert_eq!(code_of(r#"{"type":"dance"}"#), ErrorCode::UnknownType);
+        assert_eq!(code_of(r#"{"type":"ping"}"#), ErrorCode::InvalidMessage);
+        assert_eq!(
+            code_of(r#"{"type":"publish","topic":"news"}"#),
+            ErrorCode::UnknownType
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Categories for the errors a WebSocket read can end with, shared by the
+//! server and the client.
+//!
+//! A peer breaking the framing rules, sending invalid UTF-8 or exceeding the
+//! size limits gets a close frame with the matching code; a dropped transport
+//! gets nothing, since nobody is left to receive it.
+
+// Each binary only uses part of the categories
+#![allow(dead_code)]
+
+use std::io::ErrorKind;
+use tokio_tungstenite::tungstenite::error::ProtocolError;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::Error as WsError;
+
+/// What a failed read says about the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadErrorKind {
+    /// The peer broke the WebSocket framing rules
+    Protocol,
+    /// A text message was not valid UTF-8
+    InvalidUtf8,
+    /// A message or frame exceeded the size limits
+    TooBig,
+    /// The peer dropped the connection without a closing handshake
+    Reset,
+    /// The connection had already been closed
+    Closed,
+    /// The socket underneath failed
+    Transport,
+    /// Anything else, such as a TLS failure
+    Other,
+}
+
+impl ReadErrorKind {
+    pub fn of(error: &WsError) -> Self {
+        match error {
+            WsError::Protocol(ProtocolError::ResetWithoutClosingHandshake) => ReadErrorKind::Reset,
+            WsError::Protocol(_) => ReadErrorKind::Protocol,
+            WsError::Utf8 => ReadErrorKind::InvalidUtf8,
+            WsError::Capacity(_) => ReadErrorKind::TooBig,
+            WsError::ConnectionClosed | WsError::AlreadyClosed => ReadErrorKind::Closed,
+            WsError::Io(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::ConnectionReset
+                        | ErrorKind::ConnectionAborted
+                        | ErrorKind::BrokenPipe
+                ) =>
+            {
+                ReadErrorKind::Reset
+            }
+            WsError::Io(_) => ReadErrorKind::Transport,
+            _ => ReadErrorKind::Other,
+        }
+    }
+
+    /// Short description used at the start of log lines
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReadErrorKind::Protocol => "protocol violation",
+            ReadErrorKind::InvalidUtf8 => "invalid UTF-8",
+            ReadErrorKind::TooBig => "message too big",
+            ReadErrorKind::Reset => "connection reset",
+            ReadErrorKind::Closed => "connection already closed",
+            ReadErrorKind::Transport => "transport error",
+            ReadErrorKind::Other => "unexpected error",
+        }
+    }
+
+    /// The code to close with, or `None` if the transport is gone and no
+    /// close frame can be delivered
+    pub fn close_code(&self) -> Option<CloseCode> {
+        match self {
+            ReadErrorKind::Protocol => Some(CloseCode::Protocol),
+            ReadErrorKind::InvalidUtf8 => Some(CloseCode::Invalid),
+            ReadErrorKind::TooBig => Some(CloseCode::Size),
+            ReadErrorKind::Other => Some(CloseCode::Error),
+            ReadErrorKind::Reset | ReadErrorKind::Closed | ReadErrorKind::Transport => None,
+        }
+    }
+
+    /// Whether the peer misbehaved, as opposed to the connection failing
+    pub fn is_peer_fault(&self) -> bool {
+        matches!(
+            self,
+            ReadErrorKind::Protocol | ReadErrorKind::InvalidUtf8 | ReadErrorKind::TooBig
+        )
+    }
+}
+
+/// The log message and close code for a failed read, e.g.
+/// `protocol violation (WebSocket protocol error: Reserved bits are non-zero)`
+/// with 1002
+pub fn describe_read_error(error: &WsError) -> (String, Option<CloseCode>) {
+    let kind = ReadErrorKind::of(error);
+    (format!("{} ({})", kind.as_str(), error), kind.close_code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::tungstenite::error::CapacityError;
+
+    #[test]
+    fn test_read_errors_map_to_close_codes() {
+        let code = |error: WsError| describe_read_error(&error).1.map(u16::from);
+        assert_eq!(
+            code(WsError::Protocol(ProtocolError::NonZeroReservedBits)),
+            Some(1002)
+        );
+        assert_eq!(code(WsError::Utf8), Some(1007));
+        assert_eq!(
+            code(WsError::Capacity(CapacityError::MessageTooLong {
+                size: 10,
+                max_size: 5
+            })),
+            Some(1009)
+        );
+        assert_eq!(
+            code(WsError::Protocol(
+                ProtocolError::ResetWithoutClosingHandshake
+            )),
+            None
+        );
+        assert_eq!(code(WsError::ConnectionClosed), None);
+        assert_eq!(
+            code(WsError::Io(std::io::Error::from(
+                ErrorKind::ConnectionReset
+            ))),
+            None
+        );
+        assert_eq!(
+            code(WsError::Io(std::io::Error::from(ErrorKind::Other))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_read_error_descriptions_name_the_category() {
+        let (message, _) = describe_read_error(&WsError::Utf8);
+        assert!(message.starts_with("invalid UTF-8 ("), "{}", message);
+        assert_eq!(
+            ReadErrorKind::of(&WsError::Io(std::io::Error::from(
+                ErrorKind::ConnectionReset
+            ))),
+            ReadErrorKind::Reset
+        );
+        assert!(ReadErrorKind::Protocol.is_peer_fault());
+        assert!(!ReadErrorKind::Transport.is_peer_fault());
+    }
+}
@@ -0,0 +1,176 @@
+//! `BroadcastBackend` over Redis pub/sub, enabled by the `redis` feature.
+//!
+//! Every instance publishes its JSON broadcasts to one channel and subscribes
+//! to it. Publishing and subscribing run on their own connections, and each
+//! reconnects with a doubling delay when Redis goes away; meanwhile
+//! broadcasts only reach the local clients.
+
+use crate::backoff::Backoff;
+use crate::{BroadcastBackend, ClusterBroadcast};
+use futures_util::StreamExt;
+use redis::AsyncCommands;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+/// Channel used when `--redis-channel` isn't given
+pub const DEFAULT_CHANNEL: &str = "websocket-broadcast";
+
+const RETRY_DELAY_MIN: Duration = Duration::from_millis(250);
+const RETRY_DELAY_MAX: Duration = Duration::from_secs(10);
+
+pub struct RedisBackend {
+    client: redis::Client,
+    channel: String,
+    outgoing: mpsc::UnboundedSender<ClusterBroadcast>,
+    /// Taken by the publisher task when `subscribe` starts it
+    outgoing_rx: Mutex<Option<mpsc::UnboundedReceiver<ClusterBroadcast>>>,
+    publisher_connected: Arc<AtomicBool>,
+    subscriber_connected: Arc<AtomicBool>,
+}
+
+impl RedisBackend {
+    /// Only checks `url`; the connections are made once the server subscribes
+    pub fn new(url: &str, channel: &str) -> redis::RedisResult<Self> {
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        Ok(Self {
+            client: redis::Client::open(url)?,
+            channel: channel.to_string(),
+            outgoing,
+            outgoing_rx: Mutex::new(Some(outgoing_rx)),
+            publisher_connected: Arc::new(AtomicBool::new(false)),
+            subscriber_connected: Arc::new(AtomicBool::new(false)),
+        })
+    }
+}
+
+impl BroadcastBackend for RedisBackend {
+    fn publish(&self, broadcast: ClusterBroadcast) {
+        if self.publisher_connected.load(Ordering::Relaxed) {
+            let _ = self.outgoing.send(broadcast);
+        }
+    }
+
+    fn subscribe(&self, deliver: mpsc::UnboundedSender<ClusterBroadcast>) {
+        let Some(outgoing) = self.outgoing_rx.lock().unwrap().take() else {
+            warn!("Redis backend subscribed twice - ignoring");
+            return;
+        };
+        tokio::spawn(run_publisher(
+            self.client.clone(),
+            self.channel.clone(),
+            outgoing,
+            self.publisher_connected.clone(),
+        ));
+        tokio::spawn(run_subscriber(
+            self.client.clone(),
+            self.channel.clone(),
+            deliver,
+            self.subscriber_connected.clone(),
+        ));
+    }
+
+    fn is_connected(&self) -> bool {
+        self.publisher_connected.load(Ordering::Relaxed)
+            && self.subscriber_connected.load(Ordering::Relaxed)
+    }
+}
+
+async fn run_publisher(
+    client: redis::Client,
+    channel: String,
+    mut outgoing: mpsc::UnboundedReceiver<ClusterBroadcast>,
+    connected: Arc<AtomicBool>,
+) {
+    let mut backoff = Backoff::new(RETRY_DELAY_MIN, RETRY_DELAY_MAX);
+    loop {
+        match client.get_multiplexed_async_connection().await {
+            Ok(mut connection) => {
+                info!("Publishing broadcasts to Redis channel {}", channel);
+                connected.store(true, Ordering::Relaxed);
+                backoff.reset();
+                loop {
+                    // The backend itself holds a sender, so this only ends with it
+                    let Some(broadcast) = outgoing.recv().await else {
+                        return;
+                    };
+                    let payload =
+                        serde_json::to_string(&broadcast).expect("broadcasts always serialize");
+                    if let Err(e) = connection.publish::<_, _, i64>(&channel, payload).await {
+                        warn!("Redis publish failed: {}", e);
+                        break;
+                    }
+                }
+                connected.store(false, Ordering::Relaxed);
+            }
+            Err(e) => warn!("Can't connect to Redis for publishing: {}", e),
+        }
+        let retry_delay = backoff.next_delay();
+        warn!("Reconnecting the Redis publisher in {:?}", retry_delay);
+        sleep(retry_delay).await;
+    }
+}
+
+async fn run_subscriber(
+    client: redis::Client,
+    channel: String,
+    deliver: mpsc::UnboundedSender<ClusterBroadcast>,
+    connected: Arc<AtomicBool>,
+) {
+    let mut backoff = Backoff::new(RETRY_DELAY_MIN, RETRY_DELAY_MAX);
+    loop {
+        match subscribe(&client, &channel).await {
+            Ok(pubsub) => {
+                info!("Subscribed to Redis channel {}", channel);
+                connected.store(true, Ordering::Relaxed);
+                backoff.reset();
+                let mut messages = pubsub.into_on_message();
+                while let Some(message) = messages.next().await {
+                    let broadcast = message
+                        .get_payload::<String>()
+                        .map_err(|e| e.to_string())
+                        .and_then(|text| {
+                            serde_json::from_str::<ClusterBroadcast>(&text)
+                                .map_err(|e| e.to_string())
+                        });
+                    match broadcast {
+                        Ok(broadcast) => {
+                            if deliver.send(broadcast).is_err() {
+                                return;
+                            }
+                        }
+                        Err(e) => warn!("Ignoring malformed message on {}: {}", channel, e),
+                    }
+                }
+                connected.store(false, Ordering::Relaxed);
+                warn!("Lost the subscription to Redis channel {}", channel);
+            }
+            Err(e) => warn!("Can't subscribe to Redis channel {}: {}", channel, e),
+        }
+        let retry_delay = backoff.next_delay();
+        warn!("Resubscribing to Redis in {:?}", retry_delay);
+        sleep(retry_delay).await;
+    }
+}
+
+async fn subscribe(
+    client: &redis::Client,
+    channel: &str,
+) -> redis::RedisResult<redis::aio::PubSub> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(channel).await?;
+    Ok(pubsub)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_invalid_url() {
+        assert!(RedisBackend::new("not a url", DEFAULT_CHANNEL).is_err());
+        assert!(RedisBackend::new("redis://127.0.0.1:6379", DEFAULT_CHANNEL).is_ok());
+    }
+}
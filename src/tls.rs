@@ -0,0 +1,141 @@
+//! TLS termination for listeners that set `ListenerConfig::tls`.
+//!
+//! The certificate chain and key are loaded once, when `run_server` binds the
+//! listener, so a bad path stops the server at startup rather than failing
+//! every handshake. The TLS handshake itself runs on the connection's task,
+//! after any PROXY header, so a slow client can't hold up the accept loop.
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig as RustlsServerConfig;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// How long a client gets to finish the TLS handshake
+pub const TLS_HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// Certificate and key for one TLS listener, both PEM files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// The server certificate followed by any intermediates
+    pub cert_path: PathBuf,
+    /// PKCS#8, PKCS#1 or SEC1 private key for the first certificate
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+
+    /// Reads the certificate chain and key into an acceptor
+    pub fn acceptor(&self) -> io::Result<TlsAcceptor> {
+        let invalid = |path: &PathBuf, e: rustls::pki_types::pem::Error| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        };
+        let certs = CertificateDer::pem_file_iter(&self.cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| invalid(&self.cert_path, e))?;
+        if certs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: no certificates found", self.cert_path.display()),
+            ));
+        }
+        let key =
+            PrivateKeyDer::from_pem_file(&self.key_path).map_err(|e| invalid(&self.key_path, e))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = RustlsServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .expect("ring supports the default TLS versions")
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// A client connection, as accepted or after the TLS handshake
+pub enum ServerStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for ServerStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ServerStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ServerStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ServerStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ServerStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ServerStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            ServerStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acceptor_reports_the_bad_file() {
+        let dir = std::env::temp_dir().join(format!(
+            "ws-server-tls-{}-{}",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty.pem");
+        std::fs::write(&empty, "").unwrap();
+
+        let missing = TlsConfig::new(dir.join("missing.pem"), &empty);
+        let err = missing.acceptor().err().unwrap();
+        assert!(err.to_string().contains("missing.pem"), "{}", err);
+
+        let no_certs = TlsConfig::new(&empty, &empty);
+        let err = no_certs.acceptor().err().unwrap();
+        assert!(err.to_string().contains("no certificates found"), "{}", err);
+    }
+}